@@ -0,0 +1,526 @@
+use crate::{
+    error::Limit,
+    options::ParseOptions,
+    parsed::{SpanAttributes, UnderlineStyle},
+    token::Param,
+};
+use ratatui_core::{
+    style::{Modifier, Style},
+    text::Span,
+};
+use std::collections::BTreeSet;
+use unicode_width::UnicodeWidthChar;
+
+/// A grid of terminal cells with a cursor, for the modes where text can move back and overwrite
+/// what is already there, or move forward to a tab stop.
+///
+/// The line modes only use the row of the current line, which is taken at every line break. The
+/// virtual screen mode keeps all rows until the end of the input.
+#[derive(Debug, Default)]
+pub(crate) struct Screen {
+    rows: Vec<Vec<Cell>>,
+    row: usize,
+    column: usize,
+    /// Whether cursor movement and erase sequences are applied (the virtual screen mode).
+    virtual_screen: bool,
+    /// Whether a lone `\r` moves the cursor back, so EL erases what is written over.
+    overwrite: bool,
+    /// The number of columns, text wraps at the last one.
+    width: Option<usize>,
+    /// The number of rows, the screen scrolls up when the cursor moves below the last one.
+    height: Option<usize>,
+    /// The rows that can be written, from [`Limits::max_lines`](crate::Limits::max_lines).
+    max_rows: Option<usize>,
+    /// The bytes written to the cells since the last line was taken, an upper bound of the
+    /// output they make.
+    written: usize,
+    /// Whether `\b` moves the cursor left and combines the characters written over each other.
+    overstrike: bool,
+    /// The cursor was moved left with `\b`, so the next characters strike over the cells.
+    striking: bool,
+    /// The tab stops `\t` moves to, or `None` to keep tabs in the text.
+    tab_stops: Option<TabStops>,
+}
+
+/// Tab stops every `width` columns, changed by HTS and TBC.
+#[derive(Debug)]
+struct TabStops {
+    width: usize,
+    /// Tab stops set with HTS.
+    added: BTreeSet<usize>,
+    /// Regular tab stops cleared with `TBC 0`.
+    removed: BTreeSet<usize>,
+    /// All regular tab stops were cleared with `TBC 3`.
+    cleared: bool,
+}
+
+impl TabStops {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            cleared: false,
+        }
+    }
+
+    /// The first tab stop after `column`, or the next column if there is none.
+    fn next(&self, column: usize) -> usize {
+        let mut regular = (!self.cleared).then(|| (column / self.width + 1) * self.width);
+        while let Some(stop) = regular.filter(|stop| self.removed.contains(stop)) {
+            regular = Some(stop + self.width);
+        }
+        let added = self.added.range(column + 1..).next().copied();
+        match (regular, added) {
+            (Some(regular), Some(added)) => regular.min(added),
+            (regular, added) => regular.or(added).unwrap_or(column + 1),
+        }
+    }
+
+    fn set(&mut self, column: usize) {
+        self.removed.remove(&column);
+        self.added.insert(column);
+    }
+
+    fn clear(&mut self, column: usize) {
+        self.added.remove(&column);
+        if column % self.width == 0 {
+            self.removed.insert(column);
+        }
+    }
+
+    fn clear_all(&mut self) {
+        self.added.clear();
+        self.removed.clear();
+        self.cleared = true;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cell {
+    /// The character in the cell with its combining characters, or empty for the second half of
+    /// a wide character.
+    symbol: String,
+    style: Style,
+    attributes: SpanAttributes,
+}
+
+impl Cell {
+    fn blank() -> Self {
+        Self {
+            symbol: " ".to_string(),
+            style: Style::default(),
+            attributes: SpanAttributes::default(),
+        }
+    }
+
+    /// Whether this is the second half of a wide character.
+    fn is_continuation(&self) -> bool {
+        self.symbol.is_empty()
+    }
+}
+
+impl Screen {
+    /// The screen for the main text, which is a virtual screen if `options` ask for one.
+    pub(crate) fn new(options: &ParseOptions) -> Self {
+        Self::with_mode(options, options.virtual_screen.is_some())
+    }
+
+    /// A virtual screen for the alternate screen, sized like the main virtual screen if there is
+    /// one.
+    pub(crate) fn alternate(options: &ParseOptions) -> Self {
+        Self::with_mode(options, true)
+    }
+
+    fn with_mode(options: &ParseOptions, virtual_screen: bool) -> Self {
+        let (width, height) = match options.virtual_screen {
+            Some((width, height)) => (Some(width.max(1)), height.map(|height| height.max(1))),
+            None => (None, None),
+        };
+        // A terminal has tab stops every 8 columns
+        let tab_width = match options.tab_width {
+            None if virtual_screen => Some(8),
+            tab_width => tab_width,
+        };
+        Self {
+            virtual_screen,
+            overwrite: options.overwrite_on_carriage_return,
+            width,
+            height,
+            max_rows: options.limits.max_lines.filter(|_| virtual_screen),
+            overstrike: options.overstrike,
+            tab_stops: tab_width.filter(|&width| width > 0).map(TabStops::new),
+            ..Self::default()
+        }
+    }
+
+    /// The row of the cursor.
+    pub(crate) fn row(&self) -> usize {
+        self.row
+    }
+
+    /// The column of the cursor.
+    pub(crate) fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The cells of the cursor row.
+    fn cells(&mut self) -> &mut Vec<Cell> {
+        if self.rows.len() <= self.row {
+            self.rows.resize_with(self.row + 1, Vec::new);
+        }
+        &mut self.rows[self.row]
+    }
+
+    /// Set a tab stop at the cursor (HTS).
+    pub(crate) fn set_tab_stop(&mut self) {
+        if let Some(tab_stops) = &mut self.tab_stops {
+            tab_stops.set(self.column);
+        }
+    }
+
+    /// Clear the tab stop at the cursor (`TBC 0`), or all tab stops (`TBC 3`).
+    fn clear_tab_stops(&mut self, all: bool) {
+        if let Some(tab_stops) = &mut self.tab_stops {
+            if all {
+                tab_stops.clear_all();
+            } else {
+                tab_stops.clear(self.column);
+            }
+        }
+    }
+
+    /// Move the cursor back to the first column.
+    pub(crate) fn carriage_return(&mut self) {
+        self.column = 0;
+        self.striking = false;
+    }
+
+    /// Move the cursor down one row, scrolling the screen up at the last row.
+    fn line_feed(&mut self) {
+        self.row += 1;
+        self.striking = false;
+        if let Some(height) = self.height {
+            if self.row >= height {
+                if !self.rows.is_empty() {
+                    self.rows.remove(0);
+                }
+                self.row = height - 1;
+            }
+        }
+    }
+
+    /// Move the cursor to the start of the next row.
+    pub(crate) fn new_line(&mut self) {
+        self.carriage_return();
+        self.line_feed();
+    }
+
+    /// Move the cursor back one character.
+    fn backspace(&mut self) {
+        if let Some(width) = self.width {
+            self.column = self.column.min(width - 1);
+        }
+        self.column = self.previous().unwrap_or(0);
+        self.striking = true;
+    }
+
+    /// Move the cursor to `row` and `column`, keeping it on the screen.
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = self.height.map_or(row, |height| row.min(height - 1));
+        self.column = self.width.map_or(column, |width| column.min(width - 1));
+        self.striking = false;
+    }
+
+    /// Apply a control sequence that moves the cursor, erases or changes the tab stops.
+    ///
+    /// Cursor movements and erases are only applied in the virtual screen mode, except EL, which
+    /// also clears the line that `\r` returned to, as in `\r\x1b[K`. Returns whether the control
+    /// sequence was applied.
+    pub(crate) fn control_sequence(&mut self, final_byte: u8, params: &[Param]) -> bool {
+        let param = |i: usize| params.get(i).map_or(0, |param| usize::from(param.value));
+        // Cursor movements count 0 as 1
+        let n = param(0).max(1);
+        match final_byte {
+            // TBC
+            b'g' => {
+                self.clear_tab_stops(param(0) == 3);
+                return true;
+            }
+            // EL
+            b'K' if self.overwrite => {
+                self.erase_line(param(0));
+                return true;
+            }
+            _ => (),
+        }
+        if !self.virtual_screen {
+            return false;
+        }
+        let column = self
+            .width
+            .map_or(self.column, |width| self.column.min(width - 1));
+        match final_byte {
+            // CUU, CUD, CUF, CUB
+            b'A' => self.move_to(self.row.saturating_sub(n), column),
+            b'B' => self.move_to(self.row + n, column),
+            b'C' => self.move_to(self.row, column + n),
+            b'D' => self.move_to(self.row, column.saturating_sub(n)),
+            // CNL, CPL
+            b'E' => self.move_to(self.row + n, 0),
+            b'F' => self.move_to(self.row.saturating_sub(n), 0),
+            // CHA, VPA
+            b'G' => self.move_to(self.row, n - 1),
+            b'd' => self.move_to(n - 1, column),
+            // CUP, HVP
+            b'H' | b'f' => self.move_to(param(0).max(1) - 1, param(1).max(1) - 1),
+            // EL, ED
+            b'K' => self.erase_line(param(0)),
+            b'J' => self.erase_screen(param(0)),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Erase from the cursor to the end of the row (`0`), from the start of the row to the
+    /// cursor (`1`) or the whole row (`2`).
+    fn erase_line(&mut self, mode: usize) {
+        let column = self.column;
+        // A row that was never written has nothing to erase
+        let Some(cells) = self.rows.get_mut(self.row) else {
+            return;
+        };
+        match mode {
+            0 => {
+                if cells.get(column).is_some_and(Cell::is_continuation) {
+                    cells[column - 1] = Cell::blank();
+                }
+                cells.truncate(column);
+            }
+            1 => {
+                let end = (column + 1).min(cells.len());
+                if cells.get(end).is_some_and(Cell::is_continuation) {
+                    cells[end] = Cell::blank();
+                }
+                cells[..end].fill(Cell::blank());
+            }
+            2 => cells.clear(),
+            _ => (),
+        }
+    }
+
+    /// Erase from the cursor to the end of the screen (`0`), from the start of the screen to the
+    /// cursor (`1`) or the whole screen (`2` and `3`).
+    fn erase_screen(&mut self, mode: usize) {
+        match mode {
+            0 => {
+                self.erase_line(0);
+                self.rows.truncate(self.row + 1);
+            }
+            1 => {
+                self.erase_line(1);
+                for row in self.rows.iter_mut().take(self.row) {
+                    row.clear();
+                }
+            }
+            2 | 3 => self.rows.clear(),
+            _ => (),
+        }
+    }
+
+    /// Write `text` at the cursor, overwriting the cells that are already there.
+    ///
+    /// Stops at the first character that would go over a limit, and returns the limit. `budget` is
+    /// what is left of [`Limits::max_output_len`](crate::Limits::max_output_len).
+    pub(crate) fn write(
+        &mut self,
+        text: &str,
+        style: Style,
+        attributes: SpanAttributes,
+        budget: Option<usize>,
+    ) -> Result<(), Limit> {
+        for c in text.chars() {
+            if self.overstrike && c == '\x08' {
+                self.backspace();
+                continue;
+            }
+            if let (Some(tab_stops), '\t') = (&self.tab_stops, c) {
+                let column = tab_stops.next(self.column);
+                self.column = self.width.map_or(column, |width| column.min(width - 1));
+                self.striking = false;
+                continue;
+            }
+            if self.striking && self.strike(c) {
+                continue;
+            }
+            // Combining and control characters stay with the character before them
+            let width = c.width().unwrap_or(0);
+            if width == 0 {
+                if let Some(previous) = self.previous() {
+                    self.spend(c.len_utf8(), budget)?;
+                    self.cells()[previous].symbol.push(c);
+                    continue;
+                }
+            }
+            self.put(c, width.max(1), style, attributes, budget)?;
+        }
+        Ok(())
+    }
+
+    /// Count `len` more bytes as written, unless that goes over `budget`.
+    fn spend(&mut self, len: usize, budget: Option<usize>) -> Result<(), Limit> {
+        if budget.is_some_and(|max| self.written + len > max) {
+            return Err(Limit::OutputLen);
+        }
+        self.written += len;
+        Ok(())
+    }
+
+    /// The index of the cell before the cursor, skipping the second half of a wide character.
+    fn previous(&self) -> Option<usize> {
+        let i = self.column.checked_sub(1)?;
+        match self.rows.get(self.row)?.get(i) {
+            Some(cell) if cell.is_continuation() => i.checked_sub(1),
+            Some(_) => Some(i),
+            None => None,
+        }
+    }
+
+    /// Strike `c` over the cell at the cursor like a typewriter does: the same character twice is
+    /// bold, and a character together with `_` is underlined.
+    ///
+    /// Returns `false` if `c` should simply replace the cell.
+    fn strike(&mut self, c: char) -> bool {
+        let column = self.column;
+        let Some(cells) = self.rows.get_mut(self.row) else {
+            return false;
+        };
+        let Some(cell) = cells.get_mut(column) else {
+            return false;
+        };
+        let mut chars = cell.symbol.chars();
+        let (Some(existing), None) = (chars.next(), chars.next()) else {
+            return false;
+        };
+        if existing == c {
+            cell.style = cell.style.add_modifier(Modifier::BOLD);
+        } else if existing == '_' || c == '_' {
+            if existing == '_' {
+                cell.symbol = c.to_string();
+            }
+            cell.style = cell.style.add_modifier(Modifier::UNDERLINED);
+            cell.attributes.underline_style = UnderlineStyle::Single;
+        } else {
+            return false;
+        }
+        let mut column = column + 1;
+        while cells.get(column).is_some_and(Cell::is_continuation) {
+            column += 1;
+        }
+        self.column = column;
+        true
+    }
+
+    fn put(
+        &mut self,
+        c: char,
+        width: usize,
+        style: Style,
+        attributes: SpanAttributes,
+        budget: Option<usize>,
+    ) -> Result<(), Limit> {
+        // Wrap to the next row when the character doesn't fit
+        if self
+            .width
+            .is_some_and(|columns| self.column > 0 && self.column + width > columns)
+        {
+            self.new_line();
+        }
+        if self.max_rows.is_some_and(|max| self.row >= max) {
+            return Err(Limit::Lines);
+        }
+        let column = self.column;
+        let end = column + width;
+        // The cells up to the cursor are filled with spaces
+        let blanks = column.saturating_sub(self.rows.get(self.row).map_or(0, Vec::len));
+        self.spend(blanks + c.len_utf8(), budget)?;
+        let cells = self.cells();
+        if cells.len() < end {
+            cells.resize(end, Cell::blank());
+        }
+        // Overwriting half of a wide character leaves a blank in the other half
+        if cells[column].is_continuation() && column > 0 {
+            cells[column - 1].symbol = " ".to_string();
+        }
+        if cells.get(end).is_some_and(Cell::is_continuation) {
+            cells[end].symbol = " ".to_string();
+        }
+        cells[column] = Cell {
+            symbol: c.to_string(),
+            style,
+            attributes,
+        };
+        for cell in &mut cells[column + 1..end] {
+            *cell = Cell {
+                symbol: String::new(),
+                style,
+                attributes,
+            };
+        }
+        self.column = end;
+        Ok(())
+    }
+
+    /// Take the spans of the cursor row and start a new line in its place.
+    pub(crate) fn take_line(&mut self) -> (Vec<Span<'static>>, Vec<SpanAttributes>) {
+        self.carriage_return();
+        self.written = 0;
+        spans(std::mem::take(self.cells()))
+    }
+
+    /// Write the rows of `screen` on the rows from the cursor down, and move to the row after
+    /// them.
+    pub(crate) fn write_rows(&mut self, screen: Screen) {
+        if self.column > 0 {
+            self.new_line();
+        }
+        self.written += screen.written;
+        for row in screen.into_rows() {
+            if self.max_rows.is_some_and(|max| self.row >= max) {
+                break;
+            }
+            *self.cells() = row;
+            self.new_line();
+        }
+    }
+
+    /// The spans of every row, without the empty rows at the end.
+    pub(crate) fn into_lines(self) -> Vec<(Vec<Span<'static>>, Vec<SpanAttributes>)> {
+        self.into_rows().into_iter().map(spans).collect()
+    }
+
+    fn into_rows(mut self) -> Vec<Vec<Cell>> {
+        while self.rows.last().is_some_and(Vec::is_empty) {
+            self.rows.pop();
+        }
+        self.rows
+    }
+}
+
+/// The spans of a row, merging the neighbouring cells with the same style and attributes.
+fn spans(cells: Vec<Cell>) -> (Vec<Span<'static>>, Vec<SpanAttributes>) {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut attributes: Vec<SpanAttributes> = Vec::new();
+    for cell in cells {
+        match (spans.last_mut(), attributes.last()) {
+            (Some(span), Some(&last)) if span.style == cell.style && last == cell.attributes => {
+                span.content.to_mut().push_str(&cell.symbol);
+            }
+            _ => {
+                spans.push(Span::styled(cell.symbol, cell.style));
+                attributes.push(cell.attributes);
+            }
+        }
+    }
+    (spans, attributes)
+}
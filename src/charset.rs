@@ -0,0 +1,136 @@
+use crate::options::Encoding;
+use std::borrow::Cow;
+
+/// The character sets designated to G0 and G1, and which one is in use.
+///
+/// `ESC ( 0` designates the DEC Special Graphics set to G0 and `ESC ( B` designates ASCII again,
+/// `ESC )` does the same for G1. SO (`0x0E`) switches to G1 and SI (`0x0F`) back to G0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Charsets {
+    g0: Charset,
+    g1: Charset,
+    /// G1 is in use (after SO).
+    shifted: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Charset {
+    #[default]
+    Ascii,
+    /// The DEC Special Graphics set, with line drawing characters in place of `_` to `~`.
+    DecSpecialGraphics,
+}
+
+impl Charsets {
+    /// Apply `escape` if it designates a character set (`ESC ( F` or `ESC ) F`), returning
+    /// whether it did.
+    pub(crate) fn designate(&mut self, escape: &[u8]) -> bool {
+        let (g, final_byte) = match escape {
+            [b'\x1b', b'(', final_byte] => (&mut self.g0, final_byte),
+            [b'\x1b', b')', final_byte] => (&mut self.g1, final_byte),
+            _ => return false,
+        };
+        *g = match final_byte {
+            b'0' => Charset::DecSpecialGraphics,
+            _ => Charset::Ascii,
+        };
+        true
+    }
+
+    /// Apply the locking shifts SO and SI at the start of `s`, returning the rest.
+    pub(crate) fn shift<'a>(&mut self, mut s: &'a [u8]) -> &'a [u8] {
+        while let Some((&c, rest)) = s.split_first() {
+            match c {
+                b'\x0e' => self.shifted = true,
+                b'\x0f' => self.shifted = false,
+                _ => break,
+            }
+            s = rest;
+        }
+        s
+    }
+
+    /// Translate `text` from the character set in use to Unicode.
+    pub(crate) fn translate<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+        let charset = if self.shifted { self.g1 } else { self.g0 };
+        match charset {
+            Charset::Ascii => text,
+            Charset::DecSpecialGraphics if !text.bytes().any(|c| (b'_'..=b'~').contains(&c)) => {
+                text
+            }
+            Charset::DecSpecialGraphics => {
+                Cow::Owned(text.chars().map(dec_special_graphic).collect())
+            }
+        }
+    }
+}
+
+/// Decode `bytes` from a single byte `encoding` to Unicode.
+///
+/// ASCII is the same in every encoding, so text without other bytes is borrowed.
+pub(crate) fn decode(bytes: &[u8], encoding: Encoding) -> Cow<'_, str> {
+    if bytes.is_ascii() {
+        return Cow::Borrowed(std::str::from_utf8(bytes).expect("ASCII is valid UTF-8"));
+    }
+    Cow::Owned(
+        bytes
+            .iter()
+            .map(|&c| match encoding {
+                Encoding::Cp437 if c >= 0x80 => CP437[usize::from(c - 0x80)],
+                _ => char::from(c),
+            })
+            .collect(),
+    )
+}
+
+/// The Unicode equivalents of the bytes `0x80..=0xFF` in code page 437.
+#[rustfmt::skip]
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The Unicode equivalent of `c` in the DEC Special Graphics set.
+fn dec_special_graphic(c: char) -> char {
+    match c {
+        '_' => '\u{a0}',
+        '`' => '◆',
+        'a' => '▒',
+        'b' => '␉',
+        'c' => '␌',
+        'd' => '␍',
+        'e' => '␊',
+        'f' => '°',
+        'g' => '±',
+        'h' => '␤',
+        'i' => '␋',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        c => c,
+    }
+}
@@ -1,9 +1,10 @@
+use crate::parsed::UnderlineStyle;
 use ratatui_core::style::Color;
 
 /// This enum stores most types of ansi escape sequences  
 ///
 /// You can turn an escape sequence to this enum variant using
-/// AnsiCode::from(code: u8)
+/// AnsiCode::from(code: u16)
 /// This doesn't support all of them but does support most of them.  
 
 #[derive(Debug, PartialEq, Clone)]
@@ -19,6 +20,8 @@ pub enum AnsiCode {
     Italic,
     /// Set font to underline
     Underline,
+    /// Set the underline style (`4:0` to `4:5`)
+    UnderlineStyle(UnderlineStyle),
     /// Set cursor to slowblink
     SlowBlink,
     /// Set cursor to rapidblink
@@ -35,7 +38,7 @@ pub enum AnsiCode {
     AlternateFont,
     /// Choose alternate fonts 1-9
     #[allow(dead_code)]
-    AlternateFonts(u8), // = 11..19, // from 11 to 19
+    AlternateFonts(u16), // = 11..19, // from 11 to 19
     /// Fraktur ? No clue
     Fraktur,
     /// Turn off bold
@@ -67,12 +70,16 @@ pub enum AnsiCode {
     SetBackgroundColor,
     /// Default background color
     DefaultBackgroundColor, // 49
+    /// Set underline color (8-bit and 24-bit)
+    SetUnderlineColor, // 58
+    /// Default underline color
+    DefaultUnderlineColor, // 59
     /// Other / non supported escape codes
-    Code(Vec<u8>),
+    Code(Vec<u16>),
 }
 
-impl From<u8> for AnsiCode {
-    fn from(code: u8) -> Self {
+impl From<u16> for AnsiCode {
+    fn from(code: u16) -> Self {
         match code {
             0 => AnsiCode::Reset,
             1 => AnsiCode::Bold,
@@ -117,6 +124,8 @@ impl From<u8> for AnsiCode {
             47 => AnsiCode::BackgroundColor(Color::Gray),
             48 => AnsiCode::SetBackgroundColor,
             49 => AnsiCode::DefaultBackgroundColor,
+            58 => AnsiCode::SetUnderlineColor,
+            59 => AnsiCode::DefaultUnderlineColor,
             90 => AnsiCode::ForegroundColor(Color::DarkGray),
             91 => AnsiCode::ForegroundColor(Color::LightRed),
             92 => AnsiCode::ForegroundColor(Color::LightGreen),
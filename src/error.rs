@@ -1,25 +1,345 @@
+use nom::Offset as _;
+
 /// Errors returned by this crate.
-#[derive(Debug, thiserror::Error, PartialEq)]
+///
+/// Every error knows its [`Position`] in the input.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
-    /// Parsing failed.
+    /// The text of the input contains invalid UTF-8.
     ///
-    /// This is currently a formatted representation of a `nom` parse error.
-    #[error("Parse error: {0}")]
-    NomError(String),
-
-    /// The input contains invalid UTF-8.
-    #[cfg(feature = "simd")]
+    /// Use [`ParseOptions::lossy_utf8`](crate::ParseOptions::lossy_utf8) to replace it instead.
     #[error(transparent)]
-    Utf8Error(#[from] simdutf8::basic::Utf8Error),
+    Utf8Error(#[from] Utf8Error),
 
-    /// The input contains invalid UTF-8.
-    #[cfg(not(feature = "simd"))]
-    #[error(transparent)]
-    Utf8Error(#[from] std::string::FromUtf8Error),
+    /// An escape sequence couldn't be parsed, so the parse couldn't get past it.
+    #[error("malformed escape sequence {position}")]
+    MalformedEscape {
+        /// Where the escape sequence starts.
+        position: Position,
+    },
+
+    /// A sequence was rejected in [strict](crate::ParseOptions::strict) mode.
+    #[error("{reason} {position}")]
+    Rejected {
+        /// Where the sequence starts.
+        position: Position,
+        /// What is wrong with it.
+        reason: Reason,
+    },
+
+    /// The input went over one of the [`Limits`](crate::Limits).
+    #[error("{limit} exceeded {position}")]
+    LimitExceeded {
+        /// Where the limit was exceeded.
+        position: Position,
+        /// Which limit was exceeded.
+        limit: Limit,
+    },
+}
+
+impl Error {
+    /// The position in the input where the error happened.
+    pub fn position(&self) -> &Position {
+        match self {
+            Self::Utf8Error(e) => &e.position,
+            Self::MalformedEscape { position }
+            | Self::Rejected { position, .. }
+            | Self::LimitExceeded { position, .. } => position,
+        }
+    }
+
+    /// The error moved `offset` bytes and `lines` lines further, for an input that is the part
+    /// of a longer input after that many bytes and lines, and `columns` bytes into that line.
+    pub(crate) fn shifted(mut self, offset: usize, lines: usize, columns: usize) -> Self {
+        let position = match &mut self {
+            Self::Utf8Error(e) => &mut e.position,
+            Self::MalformedEscape { position }
+            | Self::Rejected { position, .. }
+            | Self::LimitExceeded { position, .. } => position,
+        };
+        if position.line == 1 {
+            position.column += columns;
+        }
+        position.offset += offset;
+        position.line += lines;
+        self
+    }
+
+    /// The error for a failed parse of `input`.
+    ///
+    /// The input of a parse error is a part of `input`, though not necessarily up to its end.
+    pub(crate) fn from_parse(input: &[u8], e: nom::Err<ParseError<'_>>) -> Self {
+        let e = match e {
+            nom::Err::Error(e) | nom::Err::Failure(e) => e,
+            nom::Err::Incomplete(_) => {
+                ParseError::new(&input[input.len()..], nom::error::ErrorKind::Eof)
+            }
+        };
+        let offset = input.offset(e.input);
+        match e.kind {
+            ParseErrorKind::Utf8 => {
+                let utf8 = std::str::from_utf8(e.input).err();
+                let offset = offset + utf8.map_or(0, |utf8| utf8.valid_up_to());
+                Self::Utf8Error(Utf8Error {
+                    position: Position::new(input, offset),
+                    error_len: utf8.and_then(|utf8| utf8.error_len()),
+                })
+            }
+            ParseErrorKind::Nom(_) => Self::MalformedEscape {
+                position: Position::new(input, offset),
+            },
+            ParseErrorKind::Rejected(reason) => Self::Rejected {
+                position: Position::new(input, offset),
+                reason,
+            },
+            ParseErrorKind::Limit(limit) => Self::LimitExceeded {
+                position: Position::new(input, offset),
+                limit,
+            },
+        }
+    }
+}
+
+/// Where an [`Error`] happened in the input.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Position {
+    /// The offset of the first offending byte in the input.
+    pub offset: usize,
+    /// The line of `offset`, counting from 1. Lines end with `\n`.
+    pub line: usize,
+    /// The column of `offset` in bytes, counting from 1.
+    pub column: usize,
+    /// Up to 16 bytes of the input from `offset` on, with the bytes that aren't printable ASCII
+    /// escaped (`\x1b[31m` for example).
+    pub snippet: String,
+}
+
+impl Position {
+    const SNIPPET_LEN: usize = 16;
+
+    pub(crate) fn new(input: &[u8], offset: usize) -> Self {
+        let (before, after) = input.split_at(offset.min(input.len()));
+        let line_start = before
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |i| i + 1);
+        Self {
+            offset,
+            line: before.iter().filter(|&&c| c == b'\n').count() + 1,
+            column: offset - line_start + 1,
+            snippet: after[..after.len().min(Self::SNIPPET_LEN)]
+                .escape_ascii()
+                .to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "at line {}, column {} (`{}`)",
+            self.line, self.column, self.snippet
+        )
+    }
+}
+
+/// What is wrong with a sequence of the input.
+///
+/// The reason of a [`Diagnostic`](crate::Diagnostic), and of an [`Error::Rejected`] in
+/// [strict](crate::ParseOptions::strict) mode. Strict mode only rejects unknown SGR codes,
+/// invalid colors and malformed sequences, the `Unsupported` reasons and
+/// [`Reason::InvalidUtf8`] only show up as diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Reason {
+    /// An SGR parameter that this crate doesn't know or can't apply, e.g. the `26` of `\x1b[26m`
+    /// or the font selection `10` of `\x1b[10m`.
+    UnknownSgr(u16),
+    /// An underline style `SGR 4:n` that this crate doesn't know, e.g. the `9` of `\x1b[4:9m`.
+    UnknownUnderlineStyle(u16),
+    /// The color type of an extended color isn't `2` (truecolor) or `5` (8-bit), e.g. the `3` of
+    /// `\x1b[38;3;1m`.
+    InvalidColorType(u16),
+    /// An extended color is missing its color type, index or RGB components, e.g.
+    /// `\x1b[38;2;255;0m`.
+    TruncatedColor,
+    /// A color index or RGB component is larger than 255.
+    ColorOutOfRange,
+    /// A control sequence (`ESC [`) ends before its final byte.
+    UnterminatedCsi,
+    /// A control sequence (`ESC [`) contains bytes that don't belong there.
+    MalformedCsi,
+    /// An operating system command (`ESC ]`) ends before its string terminator.
+    UnterminatedOsc,
+    /// A DCS, APC, PM or SOS string ends before its string terminator.
+    UnterminatedControlString,
+    /// An `ESC` that isn't followed by the rest of an escape sequence, e.g. at the end of the
+    /// input.
+    UnterminatedEscape,
+    /// A control sequence with this final byte that doesn't change the text, e.g. the `l` of
+    /// `\x1b[?25l`.
+    UnsupportedCsi(u8),
+    /// An operating system command that isn't applied, with its number, or `None` if it
+    /// doesn't start with a number.
+    UnsupportedOsc(Option<u16>),
+    /// Any other escape sequence that isn't applied, e.g. `\x1b7`.
+    UnsupportedEscape,
+    /// A DCS, APC, PM or SOS string, without a
+    /// [handler](crate::ParseOptions::on_control_string).
+    UnsupportedControlString,
+    /// Invalid UTF-8 that was replaced with U+FFFD in
+    /// [lossy](crate::ParseOptions::lossy_utf8) mode.
+    InvalidUtf8,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownSgr(code) => write!(f, "unknown SGR code {code}"),
+            Self::UnknownUnderlineStyle(style) => write!(f, "unknown underline style 4:{style}"),
+            Self::InvalidColorType(t) => write!(f, "invalid color type {t}"),
+            Self::TruncatedColor => f.write_str("truncated color"),
+            Self::ColorOutOfRange => f.write_str("color out of range"),
+            Self::UnterminatedCsi => f.write_str("unterminated control sequence"),
+            Self::MalformedCsi => f.write_str("malformed control sequence"),
+            Self::UnterminatedOsc => f.write_str("unterminated operating system command"),
+            Self::UnterminatedControlString => f.write_str("unterminated control string"),
+            Self::UnterminatedEscape => f.write_str("unterminated escape sequence"),
+            Self::UnsupportedCsi(final_byte) => write!(
+                f,
+                "unsupported control sequence ending in `{}`",
+                final_byte.escape_ascii()
+            ),
+            Self::UnsupportedOsc(Some(command)) => {
+                write!(f, "unsupported operating system command {command}")
+            }
+            Self::UnsupportedOsc(None) => f.write_str("unsupported operating system command"),
+            Self::UnsupportedEscape => f.write_str("unsupported escape sequence"),
+            Self::UnsupportedControlString => f.write_str("unsupported control string"),
+            Self::InvalidUtf8 => f.write_str("invalid utf-8"),
+        }
+    }
+}
+
+/// One of the [`Limits`](crate::Limits) of a parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Limit {
+    /// [`Limits::max_lines`](crate::Limits::max_lines)
+    Lines,
+    /// [`Limits::max_spans_per_line`](crate::Limits::max_spans_per_line)
+    SpansPerLine,
+    /// [`Limits::max_escape_len`](crate::Limits::max_escape_len)
+    EscapeLen,
+    /// [`Limits::max_output_len`](crate::Limits::max_output_len)
+    OutputLen,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Lines => "line limit",
+            Self::SpansPerLine => "span per line limit",
+            Self::EscapeLen => "escape sequence length limit",
+            Self::OutputLen => "output length limit",
+        })
+    }
+}
+
+/// The position of invalid UTF-8 in the input, like [`std::str::Utf8Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utf8Error {
+    position: Position,
+    error_len: Option<usize>,
+}
+
+impl Utf8Error {
+    /// The index of the first invalid byte in the input.
+    pub fn valid_up_to(&self) -> usize {
+        self.position.offset
+    }
+
+    /// The length of the invalid byte sequence, or `None` if the input ends in the middle of a
+    /// character.
+    pub fn error_len(&self) -> Option<usize> {
+        self.error_len
+    }
+
+    /// The position of the first invalid byte.
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+}
+
+impl std::fmt::Display for Utf8Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.error_len {
+            Some(len) => write!(f, "invalid utf-8 sequence of {len} bytes {}", self.position),
+            None => write!(f, "incomplete utf-8 byte sequence {}", self.position),
+        }
+    }
+}
+
+impl std::error::Error for Utf8Error {}
+
+/// The error of the parsers, with the part of the input where it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ParseError<'a> {
+    input: &'a [u8],
+    kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ParseErrorKind {
+    /// A `nom` parser didn't match.
+    Nom(nom::error::ErrorKind),
+    /// The text starting at the input isn't valid UTF-8.
+    Utf8,
+    /// The sequence starting at the input was rejected in strict mode.
+    Rejected(Reason),
+    /// A limit was exceeded at the input.
+    Limit(Limit),
 }
 
-impl From<nom::Err<nom::error::Error<&[u8]>>> for Error {
-    fn from(e: nom::Err<nom::error::Error<&[u8]>>) -> Self {
-        Self::NomError(format!("{:?}", e))
+impl<'a> ParseError<'a> {
+    pub(crate) fn new(input: &'a [u8], kind: nom::error::ErrorKind) -> Self {
+        Self {
+            input,
+            kind: ParseErrorKind::Nom(kind),
+        }
+    }
+
+    pub(crate) fn utf8(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            kind: ParseErrorKind::Utf8,
+        }
+    }
+
+    pub(crate) fn limit(input: &'a [u8], limit: Limit) -> Self {
+        Self {
+            input,
+            kind: ParseErrorKind::Limit(limit),
+        }
+    }
+
+    pub(crate) fn rejected(input: &'a [u8], reason: Reason) -> Self {
+        Self {
+            input,
+            kind: ParseErrorKind::Rejected(reason),
+        }
+    }
+}
+
+impl<'a> nom::error::ParseError<&'a [u8]> for ParseError<'a> {
+    fn from_error_kind(input: &'a [u8], kind: nom::error::ErrorKind) -> Self {
+        Self::new(input, kind)
+    }
+
+    fn append(_: &'a [u8], _: nom::error::ErrorKind, other: Self) -> Self {
+        other
     }
 }
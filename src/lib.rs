@@ -8,14 +8,30 @@
 //! [`Modifier`][Modifier] settings via [`Style`][Style].
 //!
 //! Unknown or malformed escape sequences are ignored, so you can feed it real terminal output
-//! without having to pre-clean it.
+//! without having to pre-clean it. Use [`ParseOptions::strict`] to reject them instead, or
+//! [`ParsedText::diagnostics`] to see what was left out.
 //!
 //! # Features
 //!
-//! - UTF-8 decoding via `String::from_utf8` (default) or [`simdutf8`][simdutf8] (`simd` feature).
+//! - UTF-8 decoding via `String::from_utf8` (default) or [`simdutf8`][simdutf8] (`simd` feature),
+//!   or CP437 and Latin-1 for ANSI art and old logs via [`ParseOptions::encoding`].
 //! - SGR styles such as bold, italic, underline, and strikethrough.
+//! - Underline styles (`\x1b[4:3m` for curly, `\x1b[4:2m` for double, ...) per span via
+//!   [`ParsedText`].
 //! - Colors: named (3/4-bit, 8/16-color), indexed (8-bit, 256-color), and truecolor (24-bit RGB).
+//! - Underline colors (`\x1b[58;2;<R>;<G>;<B>m`, reset with `\x1b[59m`) via [`ParsedText`], and
+//!   via `Style::underline_color` with the `underline-color` feature.
+//! - DEC Special Graphics line drawing (`\x1b(0lqqk`) as Unicode box drawing characters.
+//! - OSC 8 hyperlinks per span, OSC 0/1/2 title changes and OSC 133 command blocks via
+//!   [`ParsedText`].
 //! - Optional `zero-copy` API that borrows from the input.
+//! - Opt-in behavior through [`ParseOptions`], such as reading 8-bit C1 controls (`0x9B` for CSI),
+//!   applying palette redefinitions (OSC 4/10/11), overwriting progress bars on `\r`, decoding
+//!   `man` page overstrike (`X\bX`), expanding tabs, applying cursor movements on a virtual
+//!   screen or leaving out what full-screen apps draw on the alternate screen.
+//! - [`Limits`] on the lines, spans, escape sequence length and output size for untrusted input.
+//! - An [`AnsiParser`] for input that arrives in chunks, e.g. from a pipe.
+//! - A [`Tokens`] iterator to inspect, filter or rewrite the escape sequences yourself.
 //!
 //! # Supported Color Codes
 //!
@@ -28,6 +44,9 @@
 //! The SGR examples above set the foreground color (`38`). For background colors, replace `38`
 //! with `48` (for example, `\x1b[48;5;<N>m` and `\x1b[48;2;<R>;<G>;<B>m`).
 //!
+//! The colon-separated forms from ITU T.416 are accepted as well, with or without the color space
+//! id (for example, `\x1b[38:5:<N>m`, `\x1b[38:2::<R>:<G>:<B>m` and `\x1b[38:2:<R>:<G>:<B>m`).
+//!
 //! # Example
 //!
 //! The input type implements `AsRef<[u8]>`, so it is not consumed.
@@ -56,14 +75,28 @@
 //! [Modifier]: https://docs.rs/ratatui-core/latest/ratatui_core/style/struct.Modifier.html
 //! [simdutf8]: https://github.com/rusticstuff/simdutf8
 
-pub use error::Error;
+pub use error::{Error, Limit, Position, Reason, Utf8Error};
+pub use options::{AlternateScreen, Encoding, Limits, ParseOptions};
+pub use parsed::{
+    CommandBlock, Diagnostic, Hyperlink, ParsedText, SpanAttributes, Title, TitleKind,
+    UnderlineStyle,
+};
 use ratatui_core::text::Text;
+pub use stream::AnsiParser;
+pub use token::{ControlStringKind, Param, Token, Tokens};
 
+mod cells;
+mod charset;
 mod code;
 mod error;
+mod options;
+mod palette;
+mod parsed;
 mod parser;
+mod stream;
 #[cfg(test)]
 mod tests;
+mod token;
 
 /// Parse ANSI SGR styled bytes into a Ratatui [`Text`].
 ///
@@ -119,17 +152,126 @@ pub trait IntoText {
     fn to_text(&self) -> Result<Text<'_>, Error>;
 }
 
+/// Parse ANSI styled bytes into a [`ParsedText`], or following [`ParseOptions`].
+///
+/// Like [`IntoText`], this trait is implemented for all `T: AsRef<[u8]>`.
+///
+/// # Example
+///
+/// ```rust
+/// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+///
+/// let options = ParseOptions::new().overwrite_on_carriage_return(true);
+/// let text = "50%\r100%".into_text_with(&options)?;
+/// assert_eq!(text.lines[0].spans[0].content, "100%");
+/// # Ok::<(), ansi_to_tui::Error>(())
+/// ```
+pub trait IntoTextExt {
+    /// Convert the type to an owned [`ParsedText`].
+    ///
+    /// Besides the `Text`, this keeps the attributes of every span that a ratatui `Style` can't
+    /// hold, such as the [`UnderlineStyle`] set with `\x1b[4:3m`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{IntoTextExt as _, UnderlineStyle};
+    ///
+    /// let parsed = "\x1b[4:3mtypo\x1b[24m".into_parsed_text()?;
+    /// let attributes = parsed.attributes(0, 0).unwrap();
+    /// assert_eq!(attributes.underline_style, UnderlineStyle::Curly);
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    #[allow(clippy::wrong_self_convention)]
+    fn into_parsed_text(&self) -> Result<ParsedText<'static>, Error>;
+
+    /// Convert the type to a borrowed [`ParsedText`] while trying to copy as little as possible.
+    ///
+    /// See [`IntoText::to_text`] and [`IntoTextExt::into_parsed_text`].
+    #[cfg(feature = "zero-copy")]
+    fn to_parsed_text(&self) -> Result<ParsedText<'_>, Error>;
+
+    /// Like [`IntoText::into_text`], but following `options`.
+    #[allow(clippy::wrong_self_convention)]
+    fn into_text_with(&self, options: &ParseOptions) -> Result<Text<'static>, Error>;
+
+    /// Like [`IntoText::to_text`], but following `options`.
+    #[cfg(feature = "zero-copy")]
+    fn to_text_with(&self, options: &ParseOptions) -> Result<Text<'_>, Error>;
+
+    /// Like [`IntoTextExt::into_parsed_text`], but following `options`.
+    #[allow(clippy::wrong_self_convention)]
+    fn into_parsed_text_with(&self, options: &ParseOptions) -> Result<ParsedText<'static>, Error>;
+
+    /// Like [`IntoTextExt::to_parsed_text`], but following `options`.
+    #[cfg(feature = "zero-copy")]
+    fn to_parsed_text_with(&self, options: &ParseOptions) -> Result<ParsedText<'_>, Error>;
+}
+
 /// Blanket implementation for all `AsRef<[u8]>` types.
 impl<T> IntoText for T
 where
     T: AsRef<[u8]>,
 {
     fn into_text(&self) -> Result<Text<'static>, Error> {
-        Ok(crate::parser::text(self.as_ref())?.1)
+        self.into_text_with(&ParseOptions::default())
     }
 
     #[cfg(feature = "zero-copy")]
     fn to_text(&self) -> Result<Text<'_>, Error> {
-        Ok(crate::parser::text_fast(self.as_ref())?.1)
+        self.to_text_with(&ParseOptions::default())
+    }
+}
+
+/// Blanket implementation for all `AsRef<[u8]>` types.
+impl<T> IntoTextExt for T
+where
+    T: AsRef<[u8]>,
+{
+    fn into_parsed_text(&self) -> Result<ParsedText<'static>, Error> {
+        self.into_parsed_text_with(&ParseOptions::default())
+    }
+
+    #[cfg(feature = "zero-copy")]
+    fn to_parsed_text(&self) -> Result<ParsedText<'_>, Error> {
+        self.to_parsed_text_with(&ParseOptions::default())
+    }
+
+    fn into_text_with(&self, options: &ParseOptions) -> Result<Text<'static>, Error> {
+        let input = self.as_ref();
+        let (_, text) =
+            crate::parser::text(input, options).map_err(|e| Error::from_parse(input, e))?;
+        Ok(text)
+    }
+
+    #[cfg(feature = "zero-copy")]
+    fn to_text_with(&self, options: &ParseOptions) -> Result<Text<'_>, Error> {
+        let input = self.as_ref();
+        let (_, text) =
+            crate::parser::text_fast(input, options).map_err(|e| Error::from_parse(input, e))?;
+        Ok(text)
+    }
+
+    fn into_parsed_text_with(&self, options: &ParseOptions) -> Result<ParsedText<'static>, Error> {
+        let input = self.as_ref();
+        let (_, parsed) =
+            crate::parser::parsed_text(input, options).map_err(|e| Error::from_parse(input, e))?;
+        Ok(ParsedText {
+            text: crate::parser::into_owned(parsed.text),
+            alternate_screens: parsed
+                .alternate_screens
+                .into_iter()
+                .map(crate::parser::into_owned)
+                .collect(),
+            ..parsed
+        })
+    }
+
+    #[cfg(feature = "zero-copy")]
+    fn to_parsed_text_with(&self, options: &ParseOptions) -> Result<ParsedText<'_>, Error> {
+        let input = self.as_ref();
+        let (_, parsed) =
+            crate::parser::parsed_text(input, options).map_err(|e| Error::from_parse(input, e))?;
+        Ok(parsed)
     }
 }
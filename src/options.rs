@@ -0,0 +1,486 @@
+use crate::ControlStringKind;
+use std::sync::Arc;
+
+/// Options that change how the input is parsed.
+///
+/// The default options match [`IntoText::into_text`](crate::IntoText::into_text). Use them with
+/// [`IntoTextExt::into_text_with`](crate::IntoTextExt::into_text_with) and friends.
+///
+/// # Example
+///
+/// ```rust
+/// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+///
+/// let options = ParseOptions::new().c1_controls(true);
+/// let text = b"\x9b31mred".into_text_with(&options)?;
+/// # Ok::<(), ansi_to_tui::Error>(())
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    pub(crate) c1_controls: bool,
+    pub(crate) control_string_handler: Option<ControlStringHandler>,
+    pub(crate) track_palette: bool,
+    pub(crate) overwrite_on_carriage_return: bool,
+    pub(crate) overstrike: bool,
+    pub(crate) tab_width: Option<usize>,
+    pub(crate) virtual_screen: Option<(usize, Option<usize>)>,
+    pub(crate) alternate_screen: AlternateScreen,
+    pub(crate) lossy_utf8: bool,
+    pub(crate) encoding: Encoding,
+    pub(crate) strict: bool,
+    pub(crate) limits: Limits,
+}
+
+impl ParseOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat the 8-bit C1 control bytes (`0x80..=0x9F`) like their 7-bit equivalents.
+    ///
+    /// For example `0x9B` is read as CSI (`ESC [`), `0x9D` as OSC (`ESC ]`) and `0x9C` as the
+    /// string terminator. Bytes that continue a UTF-8 encoded character are never read as C1
+    /// controls, so UTF-8 text stays intact.
+    ///
+    /// Disabled by default, because in UTF-8 input these bytes are usually not meant as controls.
+    pub fn c1_controls(mut self, enabled: bool) -> Self {
+        self.c1_controls = enabled;
+        self
+    }
+
+    /// Apply the palette redefinitions of OSC 4 (indexed colors), OSC 10 (default foreground) and
+    /// OSC 11 (default background), and their resets OSC 104, 110 and 111.
+    ///
+    /// The text after a redefinition gets the redefined color as `Color::Rgb` wherever it uses
+    /// that indexed, named or default color. Named colors are the palette entries 0 to 15.
+    ///
+    /// Disabled by default, the colors are kept as written.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::style::Color;
+    ///
+    /// let options = ParseOptions::new().track_palette(true);
+    /// let text = b"\x1b]4;1;rgb:ff/80/00\x07\x1b[31morange".into_text_with(&options)?;
+    /// assert_eq!(text.lines[0].spans[0].style.fg, Some(Color::Rgb(255, 128, 0)));
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn track_palette(mut self, enabled: bool) -> Self {
+        self.track_palette = enabled;
+        self
+    }
+
+    /// Make a lone `\r` return to the first column of the line, so the following text overwrites
+    /// the text that is already there, like a terminal does.
+    ///
+    /// The characters that are not overwritten keep their style. This turns the frames of a
+    /// progress bar into the single line that was visible at the end. Wide characters take two
+    /// columns, and a wide character that is partly overwritten leaves a blank. EL (`\x1b[K`)
+    /// erases the line from the cursor, as in `\r\x1b[K`.
+    ///
+    /// Disabled by default, a lone `\r` starts a new line.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::text::Line;
+    ///
+    /// let options = ParseOptions::new().overwrite_on_carriage_return(true);
+    /// let text = b"downloading  10%\rdownloading 100%\n".into_text_with(&options)?;
+    /// assert_eq!(text.lines, [Line::from("downloading 100%")]);
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn overwrite_on_carriage_return(mut self, enabled: bool) -> Self {
+        self.overwrite_on_carriage_return = enabled;
+        self
+    }
+
+    /// Make `\b` move back one character, and decode the characters that are struck over each
+    /// other like `man`, `nroff` and `ul` output does.
+    ///
+    /// A character struck over itself (`X\bX`) is bold, and a character struck over `_`
+    /// (`_\bX`, or `X\b_`) is underlined. Any other character replaces the one under it.
+    ///
+    /// Disabled by default, backspaces are kept in the span contents.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::{style::Stylize, text::Line};
+    ///
+    /// let options = ParseOptions::new().overstrike(true);
+    /// let text = b"N\x08NA\x08AM\x08ME\x08E ls _\x08f".into_text_with(&options)?;
+    /// assert_eq!(
+    ///     text.lines,
+    ///     [Line::from(vec!["NAME".bold(), " ls ".into(), "f".underlined()])]
+    /// );
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn overstrike(mut self, enabled: bool) -> Self {
+        self.overstrike = enabled;
+        self
+    }
+
+    /// Expand `\t` to the next tab stop, with a tab stop every `width` columns.
+    ///
+    /// The columns are counted by display width, so wide characters take two columns. Tab stops
+    /// set with HTS (`ESC H`) and cleared with TBC (`CSI 0 g` and `CSI 3 g`) are honored. The
+    /// columns skipped by a tab are filled with unstyled spaces when more text follows. Without a
+    /// tab stop after the cursor, a tab moves one column.
+    ///
+    /// `None` (the default) or a width of `0` keeps tabs in the span contents.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::text::Line;
+    ///
+    /// let options = ParseOptions::new().expand_tabs(Some(8));
+    /// let text = b"name\tsize\nfile.txt\t42".into_text_with(&options)?;
+    /// assert_eq!(
+    ///     text.lines,
+    ///     [Line::from("name    size"), Line::from("file.txt        42")]
+    /// );
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn expand_tabs(mut self, width: Option<usize>) -> Self {
+        self.tab_width = width;
+        self
+    }
+
+    /// Write the input to a virtual screen of `width` columns and `height` rows, and return the
+    /// final screen contents.
+    ///
+    /// This applies the cursor movements (CSI `A`, `B`, `C`, `D`, `E`, `F`, `G`, `d`, `H` and
+    /// `f`) and erases (CSI `K` and `J`) that programs with live output use to redraw parts of the
+    /// screen. Text wraps at the last column, and the screen scrolls up when the cursor moves
+    /// below the last row. With a `height` of `None` the screen grows instead, which suits logs.
+    /// `\n` moves to the start of the next row, `\r` to the start of the current row, and tabs
+    /// move to a tab stop every 8 columns unless [`ParseOptions::expand_tabs`] says otherwise.
+    ///
+    /// The empty rows at the end of the screen are left out of the `Text`.
+    ///
+    /// Disabled by default, cursor movements and erases are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::text::Line;
+    ///
+    /// let options = ParseOptions::new().virtual_screen(80, None);
+    /// let text = b"step 1\nstep 2\n\x1b[2A\x1b[Kdone\n".into_text_with(&options)?;
+    /// assert_eq!(text.lines, [Line::from("done"), Line::from("step 2")]);
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn virtual_screen(mut self, width: usize, height: Option<usize>) -> Self {
+        self.virtual_screen = Some((width, height));
+        self
+    }
+
+    /// Choose what happens to the output of full-screen apps on the alternate screen, which is
+    /// entered and left with DEC private mode 47, 1047 or 1049 (`\x1b[?1049h` ... `\x1b[?1049l`).
+    ///
+    /// The default, [`AlternateScreen::Inline`], keeps it in the text like any other output.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{AlternateScreen, IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::text::Line;
+    ///
+    /// let options = ParseOptions::new().alternate_screen(AlternateScreen::Drop);
+    /// let text = b"$ vim\n\x1b[?1049h\x1b[H~\n~\x1b[?1049l$ ".into_text_with(&options)?;
+    /// assert_eq!(text.lines, [Line::from("$ vim"), Line::from("$ ")]);
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn alternate_screen(mut self, alternate_screen: AlternateScreen) -> Self {
+        self.alternate_screen = alternate_screen;
+        self
+    }
+
+    /// Replace invalid UTF-8 in the text with U+FFFD (`�`) and keep going.
+    ///
+    /// Disabled by default, invalid UTF-8 fails the parse with [`Error::Utf8Error`], which
+    /// points at the first invalid byte.
+    ///
+    /// [`Error::Utf8Error`]: crate::Error::Utf8Error
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{Error, IntoText as _, IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::text::Line;
+    ///
+    /// let bytes = b"caf\xe9 \x1b[1mok";
+    /// let Err(Error::Utf8Error(e)) = bytes.into_text() else { panic!() };
+    /// assert_eq!(e.valid_up_to(), 3);
+    ///
+    /// let text = bytes.into_text_with(&ParseOptions::new().lossy_utf8(true))?;
+    /// assert_eq!(text.lines[0].spans[0].content, "caf\u{fffd} ");
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn lossy_utf8(mut self, enabled: bool) -> Self {
+        self.lossy_utf8 = enabled;
+        self
+    }
+
+    /// Decode the text of the input from `encoding` instead of UTF-8.
+    ///
+    /// Classic ANSI art (`.ans` files) and old logs are often in code page 437 or Latin-1. The
+    /// escape sequences are ASCII in every encoding, so they work the same. The [`Tokens`]
+    /// iterator always reads UTF-8 and returns anything else as [`Token::Invalid`].
+    ///
+    /// [`Tokens`]: crate::Tokens
+    /// [`Token::Invalid`]: crate::Token::Invalid
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{Encoding, IntoTextExt as _, ParseOptions};
+    /// use ratatui_core::style::{Style, Stylize};
+    /// use ratatui_core::text::Span;
+    ///
+    /// let options = ParseOptions::new().encoding(Encoding::Cp437);
+    /// let text = b"\x1b[34m\xdb\xb2\xb1\xb0".into_text_with(&options)?;
+    /// assert_eq!(text.lines[0].spans[0], Span::styled("█▓▒░", Style::new().blue()));
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Fail on sequences that are normally skipped because they are unknown or malformed.
+    ///
+    /// Disabled by default. When enabled, unknown SGR codes, invalid or truncated extended
+    /// colors and unterminated control sequences, OSCs and control strings fail the parse with
+    /// [`Error::Rejected`], which says what is wrong and where. Useful to check that a tool only
+    /// emits what you expect, e.g. in golden-output tests.
+    ///
+    /// [`Error::Rejected`]: crate::Error::Rejected
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{Error, IntoText as _, IntoTextExt as _, ParseOptions, Reason};
+    ///
+    /// let bytes = b"ok\n\x1b[38;2;255;0mtruncated";
+    /// assert!(bytes.into_text().is_ok());
+    ///
+    /// let Err(Error::Rejected { position, reason }) =
+    ///     bytes.into_text_with(&ParseOptions::new().strict(true))
+    /// else {
+    ///     panic!()
+    /// };
+    /// assert_eq!(reason, Reason::TruncatedColor);
+    /// assert_eq!((position.line, position.column), (2, 1));
+    /// ```
+    pub fn strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// Limit the size of the output and of single escape sequences, e.g. for untrusted input.
+    ///
+    /// By default only escape sequences are limited, see [`Limits`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{Error, IntoTextExt as _, Limit, Limits, ParseOptions};
+    ///
+    /// let bytes = b"one\ntwo\nthree";
+    /// let limits = Limits::new().max_lines(2);
+    /// let text = bytes.into_text_with(&ParseOptions::new().limits(limits))?;
+    /// assert_eq!(text.lines.len(), 2);
+    ///
+    /// let options = ParseOptions::new().limits(limits.truncate(false));
+    /// let Err(Error::LimitExceeded { position, limit }) = bytes.into_text_with(&options) else {
+    ///     panic!()
+    /// };
+    /// assert_eq!((limit, position.line), (Limit::Lines, 3));
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Call `handler` with the kind and payload of every DCS, APC, PM and SOS string.
+    ///
+    /// These strings (sixel and kitty graphics, tmux passthrough, DECRQSS responses, ...) are
+    /// never part of the parsed text, this lets you look at them anyway.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ansi_to_tui::{ControlStringKind, IntoTextExt as _, ParseOptions};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let payloads = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = Arc::clone(&payloads);
+    /// let options = ParseOptions::new().on_control_string(move |kind, payload| {
+    ///     sink.lock().unwrap().push((kind, payload.to_vec()));
+    /// });
+    /// let text = b"\x1bP1$r0m\x1b\\text".into_text_with(&options)?;
+    /// assert_eq!(
+    ///     *payloads.lock().unwrap(),
+    ///     [(ControlStringKind::Dcs, b"1$r0m".to_vec())]
+    /// );
+    /// # Ok::<(), ansi_to_tui::Error>(())
+    /// ```
+    pub fn on_control_string(
+        mut self,
+        handler: impl Fn(ControlStringKind, &[u8]) + Send + Sync + 'static,
+    ) -> Self {
+        self.control_string_handler = Some(ControlStringHandler(Arc::new(handler)));
+        self
+    }
+}
+
+/// What to do with the output on the alternate screen, see [`ParseOptions::alternate_screen`].
+///
+/// Except for [`AlternateScreen::Inline`], the alternate screen is a virtual screen (see
+/// [`ParseOptions::virtual_screen`]) that applies cursor movements and erases, so its final frame
+/// is what the app showed last.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AlternateScreen {
+    /// Keep the output in the text, as if there was no alternate screen.
+    #[default]
+    Inline,
+    /// Leave the output out of the text, like the scrollback of a terminal does.
+    Drop,
+    /// Put the final frame of the alternate screen into the text, where the app left it.
+    FinalFrame,
+    /// Leave the output out of the text, and keep the final frame in
+    /// [`ParsedText::alternate_screens`](crate::ParsedText::alternate_screens).
+    Separate,
+}
+
+/// Limits on what a parse allocates, see [`ParseOptions::limits`].
+///
+/// When the input goes over a limit, the text is cut off there (the default), or the parse fails
+/// with [`Error::LimitExceeded`](crate::Error::LimitExceeded) if [`Limits::truncate`] is
+/// disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Limits {
+    pub(crate) max_lines: Option<usize>,
+    pub(crate) max_spans_per_line: Option<usize>,
+    pub(crate) max_escape_len: usize,
+    pub(crate) max_output_len: Option<usize>,
+    pub(crate) truncate: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_lines: None,
+            max_spans_per_line: None,
+            max_escape_len: Self::DEFAULT_MAX_ESCAPE_LEN,
+            max_output_len: None,
+            truncate: true,
+        }
+    }
+}
+
+impl Limits {
+    /// The default of [`Limits::max_escape_len`], 64 KiB.
+    pub const DEFAULT_MAX_ESCAPE_LEN: usize = 64 * 1024;
+
+    /// Create the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum number of lines of the text.
+    ///
+    /// When truncating, the text ends with the last line that fits. In the virtual screen mode
+    /// this limits the rows the cursor can reach.
+    pub fn max_lines(mut self, max: usize) -> Self {
+        self.max_lines = Some(max);
+        self
+    }
+
+    /// The maximum number of spans in one line.
+    ///
+    /// When truncating, the rest of a line that has too many spans is left out.
+    pub fn max_spans_per_line(mut self, max: usize) -> Self {
+        self.max_spans_per_line = Some(max);
+        self
+    }
+
+    /// The maximum length in bytes of a control sequence, OSC or control string.
+    ///
+    /// When truncating, a longer sequence is skipped like a malformed one, so it can't swallow
+    /// a whole document.
+    pub fn max_escape_len(mut self, max: usize) -> Self {
+        self.max_escape_len = max;
+        self
+    }
+
+    /// The maximum total length in bytes of the text of all spans.
+    ///
+    /// When truncating, the text ends with the last span that fits.
+    pub fn max_output_len(mut self, max: usize) -> Self {
+        self.max_output_len = Some(max);
+        self
+    }
+
+    /// Cut the text off at a limit (enabled by default), or fail the parse.
+    pub fn truncate(mut self, enabled: bool) -> Self {
+        self.truncate = enabled;
+        self
+    }
+}
+
+/// The encoding of the text in the input, see [`ParseOptions::encoding`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// UTF-8, invalid sequences fail the parse unless
+    /// [`ParseOptions::lossy_utf8`] is enabled.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, every byte is the Unicode character with the same number.
+    Latin1,
+    /// Code page 437, the character set of the IBM PC with its box drawing and shading blocks.
+    ///
+    /// The bytes `0x00..=0x1F` are left as controls, and with
+    /// [`ParseOptions::c1_controls`] the bytes `0x80..=0x9F` are C1 controls instead of letters.
+    Cp437,
+}
+
+/// The callback set with [`ParseOptions::on_control_string`].
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub(crate) struct ControlStringHandler(Arc<dyn Fn(ControlStringKind, &[u8]) + Send + Sync>);
+
+impl ControlStringHandler {
+    pub(crate) fn call(&self, kind: ControlStringKind, payload: &[u8]) {
+        (self.0)(kind, payload)
+    }
+}
+
+impl std::fmt::Debug for ControlStringHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ControlStringHandler")
+    }
+}
+
+impl PartialEq for ControlStringHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ControlStringHandler {}
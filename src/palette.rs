@@ -0,0 +1,187 @@
+use ratatui_core::style::{Color, Style};
+
+/// The color palette as redefined by OSC 4, 10 and 11.
+///
+/// Only the redefined entries are stored, all other colors keep resolving to themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Palette {
+    entries: [Option<Color>; 256],
+    foreground: Option<Color>,
+    background: Option<Color>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            entries: [None; 256],
+            foreground: None,
+            background: None,
+        }
+    }
+}
+
+impl Palette {
+    /// Apply a palette OSC (`4`, `10`, `11`, `104`, `110` or `111`) with its arguments.
+    ///
+    /// Queries (`?`) and color specifications that can't be parsed are ignored.
+    pub(crate) fn apply(&mut self, command: &[u8], args: &[u8]) {
+        let mut args = args.split(|&c| c == b';');
+        match command {
+            b"4" => {
+                while let (Some(index), Some(spec)) = (args.next(), args.next()) {
+                    if let (Some(index), Some(color)) = (parse_index(index), parse_color(spec)) {
+                        self.entries[usize::from(index)] = Some(color);
+                    }
+                }
+            }
+            b"104" => {
+                let mut reset_any = false;
+                for index in args.filter(|index| !index.is_empty()) {
+                    reset_any = true;
+                    if let Some(index) = parse_index(index) {
+                        self.entries[usize::from(index)] = None;
+                    }
+                }
+                if !reset_any {
+                    self.entries = [None; 256];
+                }
+            }
+            // OSC 10 may be followed by the colors for OSC 11, 12, ... in the same sequence
+            b"10" | b"11" => {
+                let dynamic = [&mut self.foreground, &mut self.background];
+                let skip = usize::from(command == b"11");
+                for (color, spec) in dynamic.into_iter().skip(skip).zip(args) {
+                    if let Some(spec) = parse_color(spec) {
+                        *color = Some(spec);
+                    }
+                }
+            }
+            b"110" => self.foreground = None,
+            b"111" => self.background = None,
+            _ => (),
+        }
+    }
+
+    /// Resolve the colors of `style` to the redefined colors.
+    pub(crate) fn resolve(&self, mut style: Style) -> Style {
+        style.fg = self.resolve_color(style.fg, self.foreground);
+        style.bg = self.resolve_color(style.bg, self.background);
+        #[cfg(feature = "underline-color")]
+        {
+            style.underline_color = self.resolve_underline(style.underline_color);
+        }
+        style
+    }
+
+    /// Resolve an underline color to the redefined color.
+    pub(crate) fn resolve_underline(&self, color: Option<Color>) -> Option<Color> {
+        self.resolve_color(color, None)
+    }
+
+    fn resolve_color(&self, color: Option<Color>, default: Option<Color>) -> Option<Color> {
+        let redefined = match color {
+            None | Some(Color::Reset) => default,
+            Some(Color::Indexed(index)) => self.entries[usize::from(index)],
+            Some(color) => named_index(color).and_then(|index| self.entries[index]),
+        };
+        redefined.or(color)
+    }
+}
+
+/// The palette index of the named (4-bit) colors.
+fn named_index(color: Color) -> Option<usize> {
+    let index = match color {
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::Gray => 7,
+        Color::DarkGray => 8,
+        Color::LightRed => 9,
+        Color::LightGreen => 10,
+        Color::LightYellow => 11,
+        Color::LightBlue => 12,
+        Color::LightMagenta => 13,
+        Color::LightCyan => 14,
+        Color::White => 15,
+        _ => return None,
+    };
+    Some(index)
+}
+
+fn parse_index(index: &[u8]) -> Option<u8> {
+    std::str::from_utf8(index).ok()?.parse().ok()
+}
+
+/// An X11 color specification: `rgb:<r>/<g>/<b>` with 1 to 4 hex digits per component, or
+/// `#<rgb>` with 1 to 4 hex digits per component.
+fn parse_color(spec: &[u8]) -> Option<Color> {
+    let spec = std::str::from_utf8(spec).ok()?;
+    let components: Vec<&str> = if let Some(rgb) = spec.strip_prefix("rgb:") {
+        rgb.split('/').collect()
+    } else if let Some(hex) = spec.strip_prefix('#') {
+        // Checking the digits first also keeps the slicing below on character boundaries
+        if !hex.bytes().all(|c| c.is_ascii_hexdigit())
+            || hex.is_empty()
+            || hex.len() % 3 != 0
+            || hex.len() > 12
+        {
+            return None;
+        }
+        let width = hex.len() / 3;
+        (0..3).map(|i| &hex[i * width..(i + 1) * width]).collect()
+    } else {
+        return None;
+    };
+    let [r, g, b] = components.as_slice() else {
+        return None;
+    };
+    Some(Color::Rgb(
+        parse_component(r, spec.starts_with('#'))?,
+        parse_component(g, spec.starts_with('#'))?,
+        parse_component(b, spec.starts_with('#'))?,
+    ))
+}
+
+/// A hex color component scaled to 8 bits.
+///
+/// `rgb:` components are scaled (`f` is `ff`), while `#` components are the most significant
+/// bits (`#f00` is `#f00000000`, which is `f0` in 8 bits).
+fn parse_component(hex: &str, truncate: bool) -> Option<u8> {
+    if hex.is_empty() || hex.len() > 4 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let bits = 4 * hex.len() as u32;
+    let value = if truncate {
+        if bits >= 8 {
+            value >> (bits - 8)
+        } else {
+            value << (8 - bits)
+        }
+    } else {
+        let max = (1 << bits) - 1;
+        (value * 255 + max / 2) / max
+    };
+    u8::try_from(value).ok()
+}
+
+#[test]
+fn parse_color_test() {
+    assert_eq!(parse_color(b"rgb:ff/00/80"), Some(Color::Rgb(255, 0, 128)));
+    assert_eq!(parse_color(b"rgb:f/0/8"), Some(Color::Rgb(255, 0, 136)));
+    assert_eq!(
+        parse_color(b"rgb:ffff/0000/8080"),
+        Some(Color::Rgb(255, 0, 128))
+    );
+    assert_eq!(parse_color(b"#ff0080"), Some(Color::Rgb(255, 0, 128)));
+    assert_eq!(parse_color(b"#f08"), Some(Color::Rgb(240, 0, 128)));
+    assert_eq!(parse_color(b"#fff000888"), Some(Color::Rgb(255, 0, 136)));
+    assert_eq!(parse_color(b"?"), None);
+    assert_eq!(parse_color(b"red"), None);
+    assert_eq!(parse_color(b"rgb:ff/00"), None);
+    assert_eq!(parse_color("#é0".as_bytes()), None);
+}
@@ -0,0 +1,199 @@
+use crate::Reason;
+use ratatui_core::{style::Color, text::Text};
+use std::ops::Range;
+
+/// A Ratatui [`Text`] together with the attributes that don't fit into a ratatui `Style`.
+///
+/// Use [`IntoTextExt::into_parsed_text`](crate::IntoTextExt::into_parsed_text) to get one.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct ParsedText<'a> {
+    /// The parsed text, identical to what [`IntoText::into_text`](crate::IntoText::into_text)
+    /// returns.
+    pub text: Text<'a>,
+
+    /// The extra attributes of every span, indexed the same way as the spans of `text`.
+    ///
+    /// `span_attributes[line][span]` belongs to `text.lines[line].spans[span]`.
+    pub span_attributes: Vec<Vec<SpanAttributes>>,
+
+    /// The distinct OSC 8 hyperlinks, referenced by [`SpanAttributes::hyperlink`].
+    pub hyperlinks: Vec<Hyperlink>,
+
+    /// Every window and icon title change (OSC 0, 1 and 2), in order.
+    pub titles: Vec<Title>,
+
+    /// The shell commands marked with OSC 133 semantic prompts, in order.
+    pub command_blocks: Vec<CommandBlock>,
+
+    /// The final frame of every use of the alternate screen, in order.
+    ///
+    /// Only filled with [`AlternateScreen::Separate`](crate::AlternateScreen::Separate).
+    pub alternate_screens: Vec<Text<'a>>,
+
+    /// Every sequence that was left out of the text or only partly understood, in order.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ParsedText<'_> {
+    /// Returns the attributes of the span at `span` in line `line`, if there is such a span.
+    pub fn attributes(&self, line: usize, span: usize) -> Option<&SpanAttributes> {
+        self.span_attributes.get(line)?.get(span)
+    }
+
+    /// Returns the last window title that was set, if any.
+    ///
+    /// This is the title a terminal would show after displaying the whole input.
+    pub fn title(&self) -> Option<&str> {
+        self.titles
+            .iter()
+            .rev()
+            .find(|title| title.kind != TitleKind::Icon)
+            .map(|title| title.title.as_str())
+    }
+
+    /// Returns the hyperlink of the span at `span` in line `line`, if it is part of one.
+    pub fn hyperlink(&self, line: usize, span: usize) -> Option<&Hyperlink> {
+        let index = self.attributes(line, span)?.hyperlink?;
+        self.hyperlinks.get(index)
+    }
+}
+
+/// Attributes of a span that can't be expressed with a ratatui `Style`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct SpanAttributes {
+    /// The underline style set with `SGR 4` / `SGR 4:n`.
+    ///
+    /// Spans with an underline style other than [`UnderlineStyle::None`] also have
+    /// `Modifier::UNDERLINED` set on their style.
+    pub underline_style: UnderlineStyle,
+
+    /// The underline color set with `SGR 58`, or `None` if it was never set or reset with
+    /// `SGR 59`.
+    ///
+    /// With the `underline-color` feature this is also available as `Style::underline_color`.
+    pub underline_color: Option<Color>,
+
+    /// The index of the OSC 8 hyperlink in [`ParsedText::hyperlinks`] that the span is part of.
+    pub hyperlink: Option<usize>,
+}
+
+/// A hyperlink set with OSC 8 (`\x1b]8;id=<ID>;<URI>\x1b\\`).
+///
+/// Spans of the same link that are split by style changes or line breaks share one
+/// `Hyperlink`. Links with the same `id` are meant to be highlighted together.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Hyperlink {
+    /// The target of the link.
+    pub uri: String,
+    /// The optional `id` parameter.
+    pub id: Option<String>,
+}
+
+/// A window or icon title change, set with OSC 0, 1 or 2 (`\x1b]0;<TITLE>\x07`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Title {
+    /// Which title was changed.
+    pub kind: TitleKind,
+    /// The new title.
+    pub title: String,
+    /// The index of the line in [`ParsedText::text`] where the title was changed.
+    pub line: usize,
+}
+
+/// A shell prompt, its command and the command output, marked with OSC 133 (FinalTerm)
+/// semantic prompts.
+///
+/// | OSC            | Mark                                      |
+/// | -------------- | ----------------------------------------- |
+/// | `133;A`        | start of the prompt, starts a new block   |
+/// | `133;B`        | end of the prompt, start of the command   |
+/// | `133;C`        | end of the command, start of the output   |
+/// | `133;D;<EXIT>` | end of the output, with the exit code     |
+///
+/// All line indices refer to the lines of [`ParsedText::text`]. A block without `D` ends where
+/// the next block starts, or at the end of the text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct CommandBlock {
+    /// The lines of the whole block, from the prompt to the end of the output.
+    pub lines: Range<usize>,
+    /// The line where the command starts (`B`), if it was marked.
+    pub command: Option<usize>,
+    /// The line where the output starts (`C`), if it was marked.
+    pub output: Option<usize>,
+    /// The exit code from `D;<EXIT>`, if the command finished with one.
+    pub exit_code: Option<i32>,
+}
+
+/// A sequence of the input that was left out of the text or only partly understood.
+///
+/// Unknown and malformed sequences don't fail the parse (unless it is
+/// [strict](crate::ParseOptions::strict)), these tell you what the parsed text is missing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    /// The bytes of the sequence in the input.
+    pub range: Range<usize>,
+    /// What is wrong with the sequence.
+    pub reason: Reason,
+}
+
+/// Which title an OSC title change sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TitleKind {
+    /// OSC 0 sets both the icon name and the window title.
+    IconAndWindow,
+    /// OSC 1 sets the icon name.
+    Icon,
+    /// OSC 2 sets the window title.
+    Window,
+}
+
+/// The style of an underline, as set by the `SGR 4:n` sub-parameter.
+///
+/// | SGR   | Style                      |
+/// | ----- | -------------------------- |
+/// | `4:0` | [`UnderlineStyle::None`]   |
+/// | `4:1` | [`UnderlineStyle::Single`] |
+/// | `4:2` | [`UnderlineStyle::Double`] |
+/// | `4:3` | [`UnderlineStyle::Curly`]  |
+/// | `4:4` | [`UnderlineStyle::Dotted`] |
+/// | `4:5` | [`UnderlineStyle::Dashed`] |
+///
+/// A plain `SGR 4` is a single underline and `SGR 24` turns the underline off.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UnderlineStyle {
+    /// Not underlined
+    #[default]
+    None,
+    /// A single straight line
+    Single,
+    /// Two straight lines
+    Double,
+    /// A wavy line, often used for spelling errors and diagnostics
+    Curly,
+    /// A dotted line
+    Dotted,
+    /// A dashed line
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// The underline style for the sub-parameter `n` of `SGR 4:n`.
+    pub(crate) fn from_sgr(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Self::None),
+            1 => Some(Self::Single),
+            2 => Some(Self::Double),
+            3 => Some(Self::Curly),
+            4 => Some(Self::Dotted),
+            5 => Some(Self::Dashed),
+            _ => None,
+        }
+    }
+}
@@ -1,18 +1,28 @@
-use crate::code::AnsiCode;
+use crate::{
+    cells::Screen,
+    charset::{self, Charsets},
+    code::AnsiCode,
+    error::{Limit, ParseError, Reason},
+    options::{AlternateScreen, Encoding, ParseOptions},
+    palette::Palette,
+    parsed::{
+        CommandBlock, Diagnostic, Hyperlink, ParsedText, SpanAttributes, Title, TitleKind,
+        UnderlineStyle,
+    },
+    token::{ControlStringKind, Param, Token},
+};
 use nom::{
-    AsChar, IResult, Parser,
+    Parser,
     branch::alt,
     bytes::complete::*,
     character::complete::*,
-    combinator::{map_res, opt},
-    multi::*,
-    sequence::{delimited, preceded},
+    combinator::{opt, recognize, verify},
 };
 use ratatui_core::{
     style::{Color, Modifier, Style, Stylize},
     text::{Line, Span, Text},
 };
-use std::str::FromStr;
+use std::{borrow::Cow, collections::HashMap, ops::Range, str::FromStr};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum ColorType {
@@ -32,19 +42,195 @@ struct AnsiItem {
 struct AnsiStates {
     pub items: smallvec::SmallVec<[AnsiItem; 2]>,
     pub style: Style,
+    pub underline_style: UnderlineStyle,
+    pub underline_color: Option<Color>,
+}
+
+/// The graphic rendition carried from one span to the next.
+///
+/// This is the ratatui [`Style`] plus the attributes that `Style` has no room for.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Rendition {
+    pub style: Style,
+    pub underline_style: UnderlineStyle,
+    pub underline_color: Option<Color>,
+    /// The index of the active OSC 8 hyperlink in [`Context::hyperlinks`].
+    pub hyperlink: Option<usize>,
+}
+
+impl Rendition {
+    fn attributes(&self) -> SpanAttributes {
+        SpanAttributes {
+            underline_style: self.underline_style,
+            underline_color: self.underline_color,
+            hyperlink: self.hyperlink,
+        }
+    }
+}
+
+/// The state of a parse that outlives a single span or line.
+#[derive(Debug, Default)]
+pub(crate) struct Context {
+    /// The address of the input, to find the offsets of diagnostics.
+    input: usize,
+    /// The index of the line that is being parsed.
+    line: usize,
+    hyperlinks: Vec<Hyperlink>,
+    hyperlink_indices: HashMap<Hyperlink, usize>,
+    titles: Vec<Title>,
+    palette: Palette,
+    /// The cells being written in the modes that move the cursor, see [`Screen`].
+    screen: Screen,
+    /// The alternate screen while it is in use.
+    alternate: Option<Screen>,
+    charsets: Charsets,
+    /// Whether the current line has text before the current position.
+    line_started: bool,
+    command_blocks: Vec<CommandBlock>,
+    /// The last command block hasn't ended yet.
+    command_running: bool,
+    diagnostics: Vec<Diagnostic>,
+    /// The length of the text of all spans so far.
+    output_len: usize,
+    /// A limit was reached, the parse stops.
+    truncated: bool,
+    /// The lines that were taken out of the output of earlier parses, see [`Context::take`].
+    taken_lines: usize,
+    /// Only the text is wanted, so the span attributes, hyperlinks and diagnostics aren't
+    /// collected.
+    text_only: bool,
+}
+
+impl Context {
+    pub(crate) fn new(input: &[u8], options: &ParseOptions) -> Self {
+        Self {
+            input: input.as_ptr() as usize,
+            screen: Screen::new(options),
+            ..Self::default()
+        }
+    }
+
+    /// A context for a parse that only wants the text, see [`Context::text_only`].
+    pub(crate) fn text_only(input: &[u8], options: &ParseOptions) -> Self {
+        Self {
+            text_only: true,
+            ..Self::new(input, options)
+        }
+    }
+
+    /// Continue with `input`, for parsing input that arrives in parts.
+    pub(crate) fn set_input(&mut self, input: &[u8]) {
+        self.input = input.as_ptr() as usize;
+    }
+
+    /// Forget the output of a parse of a part of the input that ended with `lines` lines, so
+    /// the state doesn't grow with the input.
+    pub(crate) fn take(&mut self, lines: usize) {
+        self.taken_lines += lines;
+        self.titles.clear();
+        self.command_blocks.clear();
+        self.diagnostics.clear();
+    }
+
+    /// Whether a limit was reached, so the parse stopped.
+    pub(crate) fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The number of lines in the output so far.
+    fn line_count(&self, parsed: &ParsedText<'_>) -> usize {
+        self.taken_lines + parsed.text.lines.len()
+    }
+
+    /// The index of `hyperlink` in `hyperlinks`, adding it if it is new.
+    fn hyperlink(&mut self, hyperlink: Hyperlink) -> usize {
+        if let Some(&index) = self.hyperlink_indices.get(&hyperlink) {
+            return index;
+        }
+        let index = self.hyperlinks.len();
+        self.hyperlinks.push(hyperlink.clone());
+        self.hyperlink_indices.insert(hyperlink, index);
+        index
+    }
+
+    /// Apply an OSC 133 semantic prompt mark.
+    fn semantic_prompt(&mut self, args: &[u8]) {
+        let mut args = args.split(|&c| c == b';');
+        let mark = args.next().unwrap_or_default();
+        if mark == b"A" || !self.command_running {
+            self.end_command_block();
+            self.command_blocks.push(CommandBlock {
+                lines: self.line..self.line,
+                command: None,
+                output: None,
+                exit_code: None,
+            });
+            self.command_running = true;
+        }
+        let Some(block) = self.command_blocks.last_mut() else {
+            return;
+        };
+        match mark {
+            b"B" => block.command = Some(self.line),
+            b"C" => block.output = Some(self.line),
+            b"D" => {
+                block.exit_code = args
+                    .next()
+                    .and_then(|code| std::str::from_utf8(code).ok()?.parse().ok());
+                self.end_command_block();
+            }
+            _ => (),
+        }
+    }
+
+    /// Note that `sequence`, a part of the input, was left out or only partly understood.
+    fn diagnostic(&mut self, sequence: &[u8], reason: Reason) {
+        if self.text_only {
+            return;
+        }
+        let start = sequence.as_ptr() as usize - self.input;
+        self.diagnostics.push(Diagnostic {
+            range: start..start + sequence.len(),
+            reason,
+        });
+    }
+
+    /// End the running command block before the current position.
+    fn end_command_block(&mut self) {
+        if !std::mem::take(&mut self.command_running) {
+            return;
+        }
+        if let Some(block) = self.command_blocks.last_mut() {
+            block.lines.end = self.line + usize::from(self.line_started);
+        }
+    }
 }
 
 impl From<AnsiStates> for ratatui_core::style::Style {
+    fn from(states: AnsiStates) -> Self {
+        Rendition::from(states).style
+    }
+}
+
+impl From<AnsiStates> for Rendition {
     fn from(states: AnsiStates) -> Self {
         let mut style = states.style;
+        let mut underline_style = states.underline_style;
+        let mut underline_color = states.underline_color;
         if states.items.is_empty() {
             // https://github.com/uttarayan21/ansi-to-tui/issues/40
             // [m should be treated as a reset as well
             style = Style::reset();
+            underline_style = UnderlineStyle::None;
+            underline_color = None;
         }
         for item in states.items {
             match item.code {
-                AnsiCode::Reset => style = Style::reset(),
+                AnsiCode::Reset => {
+                    style = Style::reset();
+                    underline_style = UnderlineStyle::None;
+                    underline_color = None;
+                }
                 AnsiCode::Bold => style = style.add_modifier(Modifier::BOLD),
                 AnsiCode::Faint => style = style.add_modifier(Modifier::DIM),
                 AnsiCode::Normal => {
@@ -52,8 +238,18 @@ impl From<AnsiStates> for ratatui_core::style::Style {
                 }
                 AnsiCode::Italic => style = style.add_modifier(Modifier::ITALIC),
                 AnsiCode::NotItalic => style = style.remove_modifier(Modifier::ITALIC),
-                AnsiCode::Underline => style = style.add_modifier(Modifier::UNDERLINED),
-                AnsiCode::UnderlineOff => style = style.remove_modifier(Modifier::UNDERLINED),
+                AnsiCode::Underline => {
+                    style = style.add_modifier(Modifier::UNDERLINED);
+                    underline_style = UnderlineStyle::Single;
+                }
+                AnsiCode::UnderlineOff | AnsiCode::UnderlineStyle(UnderlineStyle::None) => {
+                    style = style.remove_modifier(Modifier::UNDERLINED);
+                    underline_style = UnderlineStyle::None;
+                }
+                AnsiCode::UnderlineStyle(underline) => {
+                    style = style.add_modifier(Modifier::UNDERLINED);
+                    underline_style = underline;
+                }
                 AnsiCode::SlowBlink => style = style.add_modifier(Modifier::SLOW_BLINK),
                 AnsiCode::RapidBlink => style = style.add_modifier(Modifier::RAPID_BLINK),
                 AnsiCode::BlinkOff => {
@@ -77,270 +273,1369 @@ impl From<AnsiStates> for ratatui_core::style::Style {
                         style = style.bg(color)
                     }
                 }
+                AnsiCode::SetUnderlineColor => {
+                    if let Some(color) = item.color {
+                        #[cfg(feature = "underline-color")]
+                        {
+                            style = style.underline_color(color);
+                        }
+                        underline_color = Some(color);
+                    }
+                }
+                AnsiCode::DefaultUnderlineColor => {
+                    #[cfg(feature = "underline-color")]
+                    {
+                        style = style.underline_color(Color::Reset);
+                    }
+                    underline_color = None;
+                }
                 AnsiCode::ForegroundColor(color) => style = style.fg(color),
                 AnsiCode::BackgroundColor(color) => style = style.bg(color),
                 _ => (),
             }
         }
-        style
+        Rendition {
+            style,
+            underline_style,
+            underline_color,
+            hyperlink: None,
+        }
     }
 }
 
-pub(crate) fn text(mut s: &[u8]) -> IResult<&[u8], Text<'static>> {
-    let mut lines = Vec::new();
-    let mut last = Style::new();
-    while let Ok((_s, (line, style))) = line(last)(s) {
-        lines.push(line);
-        last = style;
-        s = _s;
-        if s.is_empty() {
-            break;
+/// The result of the parsers.
+pub(crate) type IResult<'a, O> = nom::IResult<&'a [u8], O, ParseError<'a>>;
+
+/// A line that a switch to or from the alternate screen interrupted before its line break, with
+/// the attributes of its spans. It continues after the switch.
+pub(crate) type OpenLine<'a> = Option<(Line<'a>, Vec<SpanAttributes>)>;
+
+pub(crate) fn text<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Text<'static>> {
+    let (s, text) = text_fast(s, options)?;
+    Ok((s, into_owned(text)))
+}
+
+pub(crate) fn text_fast<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Text<'a>> {
+    let mut parsed = ParsedText::default();
+    let mut context = Context::text_only(s, options);
+    let mut open = None;
+    let (s, _) = parse(
+        s,
+        Rendition::default(),
+        &mut open,
+        options,
+        &mut context,
+        &mut parsed,
+    )?;
+    finish(s, &mut open, options, &mut context, &mut parsed)?;
+    Ok((s, parsed.text))
+}
+
+pub(crate) fn parsed_text<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, ParsedText<'a>> {
+    let mut parsed = ParsedText::default();
+    let mut context = Context::new(s, options);
+    let mut open = None;
+    let (s, _) = parse(
+        s,
+        Rendition::default(),
+        &mut open,
+        options,
+        &mut context,
+        &mut parsed,
+    )?;
+    finish(s, &mut open, options, &mut context, &mut parsed)?;
+    Ok((
+        s,
+        ParsedText {
+            hyperlinks: context.hyperlinks,
+            titles: context.titles,
+            command_blocks: context.command_blocks,
+            diagnostics: context.diagnostics,
+            ..parsed
+        },
+    ))
+}
+
+/// Parse `s` into `parsed`, returning the part that couldn't be parsed and the rendition at the
+/// end.
+///
+/// What is written on the virtual or alternate screen, and the `open` line, stay until
+/// [`finish`].
+pub(crate) fn parse<'a>(
+    mut s: &'a [u8],
+    mut last: Rendition,
+    open: &mut OpenLine<'a>,
+    options: &ParseOptions,
+    context: &mut Context,
+    parsed: &mut ParsedText<'a>,
+) -> IResult<'a, Rendition> {
+    loop {
+        let switch = match options.alternate_screen {
+            AlternateScreen::Inline => None,
+            _ => alternate_screen_switch(s, options),
+        };
+        let segment = switch.as_ref().map_or(s, |(range, _)| &s[..range.start]);
+        let rest = if let Some(mut screen) = context.alternate.take() {
+            std::mem::swap(&mut context.screen, &mut screen);
+            let result = self::screen(segment, last, options, context);
+            std::mem::swap(&mut context.screen, &mut screen);
+            context.alternate = Some(screen);
+            let rest;
+            (rest, last) = result?;
+            rest
+        } else if options.virtual_screen.is_some() {
+            let rest;
+            (rest, last) = screen(segment, last, options, context)?;
+            rest
+        } else if segment.is_empty() && switch.is_some() {
+            segment
+        } else {
+            let rest;
+            let keep_open = switch.is_some();
+            (rest, last) = lines(segment, last, open, keep_open, options, context, parsed)?;
+            rest
+        };
+        let Some((range, enter)) = switch.filter(|_| !context.truncated) else {
+            return Ok((rest, last));
+        };
+        let switch = &s[range.start..];
+        s = &s[range.end..];
+        if enter {
+            context
+                .alternate
+                .get_or_insert_with(|| Screen::alternate(options));
+        } else if let Some(screen) = context.alternate.take() {
+            end_alternate_screen(switch, screen, open, options, context, parsed)?;
         }
     }
-    Ok((s, Text::from(lines)))
 }
 
-#[cfg(feature = "zero-copy")]
-pub(crate) fn text_fast(mut s: &[u8]) -> IResult<&[u8], Text<'_>> {
-    let mut lines = Vec::new();
-    let mut last = Style::new();
-    while let Ok((_s, (line, style))) = line_fast(last)(s) {
-        lines.push(line);
-        last = style;
+/// Add the `open` line and what is left on the virtual and alternate screens to `parsed` at the
+/// end `s` of the input, and end the last command block.
+pub(crate) fn finish<'a>(
+    s: &'a [u8],
+    open: &mut OpenLine<'a>,
+    options: &ParseOptions,
+    context: &mut Context,
+    parsed: &mut ParsedText<'a>,
+) -> Result<(), nom::Err<ParseError<'a>>> {
+    let end = &s[s.len()..];
+    if let Some(screen) = context.alternate.take() {
+        end_alternate_screen(end, screen, open, options, context, parsed)?;
+    }
+    end_line(end, open, options, context, parsed)?;
+    if options.virtual_screen.is_some() {
+        for (spans, attributes) in std::mem::take(&mut context.screen).into_lines() {
+            if !push_line(end, Line::from(spans), attributes, options, context, parsed)? {
+                break;
+            }
+        }
+    }
+    context.line = context.line_count(parsed);
+    context.line_started = false;
+    context.end_command_block();
+    Ok(())
+}
+
+/// Parse the lines of `s` into `parsed`, returning the part that couldn't be parsed and the
+/// rendition at the end.
+///
+/// The first line continues the `open` line. If `keep_open`, a last line without a line break
+/// becomes the `open` line.
+fn lines<'a>(
+    mut s: &'a [u8],
+    mut last: Rendition,
+    open: &mut OpenLine<'a>,
+    keep_open: bool,
+    options: &ParseOptions,
+    context: &mut Context,
+    parsed: &mut ParsedText<'a>,
+) -> IResult<'a, Rendition> {
+    loop {
+        let (_s, (mut line, mut attributes, rendition)) =
+            malformed_at_error(s, line(s, last, options, context))?;
+        if let Some((start, start_attributes)) = open.take() {
+            line.spans.splice(..0, start.spans);
+            attributes.splice(..0, start_attributes);
+        }
+        if keep_open && _s.is_empty() && !matches!(s.last(), Some(b'\n' | b'\r')) {
+            *open = Some((line, attributes));
+            last = rendition;
+            s = _s;
+            break;
+        }
+        if !push_line(s, line, attributes, options, context, parsed)? {
+            break;
+        }
+        context.line = context.line_count(parsed);
+        context.line_started = false;
+        last = rendition;
         s = _s;
-        if s.is_empty() {
+        if s.is_empty() || context.truncated {
             break;
         }
     }
-    Ok((s, Text::from(lines)))
+    Ok((s, last))
 }
 
-fn newline(s: &[u8]) -> IResult<&[u8], ()> {
-    let (s, _) = alt((tag("\r\n"), tag("\n"), tag("\r"))).parse(s)?;
-    Ok((s, ()))
+/// Add a line that starts at `s` to the text, cut off at the limits.
+///
+/// Returns whether the whole line was added, otherwise the text is truncated.
+fn push_line<'a>(
+    s: &'a [u8],
+    mut line: Line<'a>,
+    mut attributes: Vec<SpanAttributes>,
+    options: &ParseOptions,
+    context: &mut Context,
+    parsed: &mut ParsedText<'a>,
+) -> Result<bool, nom::Err<ParseError<'a>>> {
+    let limits = &options.limits;
+    if limits
+        .max_lines
+        .is_some_and(|max| context.line_count(parsed) >= max)
+    {
+        cut_off(s, Limit::Lines, options, context)?;
+        return Ok(false);
+    }
+    if let Some(max) = limits
+        .max_spans_per_line
+        .filter(|&max| line.spans.len() > max)
+    {
+        if !limits.truncate {
+            return Err(nom::Err::Failure(ParseError::limit(s, Limit::SpansPerLine)));
+        }
+        line.spans.truncate(max);
+        attributes.truncate(max);
+    }
+    let mut complete = true;
+    // Without the limit the length of the output isn't needed
+    if let Some(max) = limits.max_output_len {
+        let len: usize = line.spans.iter().map(|span| span.content.len()).sum();
+        if context.output_len + len > max {
+            cut_off(s, Limit::OutputLen, options, context)?;
+            let fitting = line
+                .spans
+                .iter()
+                .scan(context.output_len, |output_len, span| {
+                    *output_len += span.content.len();
+                    Some(*output_len)
+                })
+                .take_while(|&output_len| output_len <= max)
+                .count();
+            line.spans.truncate(fitting);
+            attributes.truncate(fitting);
+            complete = false;
+        }
+        context.output_len += line
+            .spans
+            .iter()
+            .map(|span| span.content.len())
+            .sum::<usize>();
+    }
+    parsed.text.lines.push(line);
+    if !context.text_only {
+        parsed.span_attributes.push(attributes);
+    }
+    Ok(complete)
 }
 
-fn line(style: Style) -> impl Fn(&[u8]) -> IResult<&[u8], (Line<'static>, Style)> {
-    // let style_: Style = Default::default();
-    move |s: &[u8]| -> IResult<&[u8], (Line<'static>, Style)> {
-        let (s, mut text) = take_while(|c| c != b'\n' && c != b'\r').parse(s)?;
-        let (s, _) = opt(newline).parse(s)?;
-        let mut spans = Vec::new();
-        let mut last = style;
-        while let Ok((s, span)) = span(last)(text) {
-            // Since reset now tracks seperately we can skip the reset check
-            last = last.patch(span.style);
+/// Stop the parse at `limit`, or fail at `s` if the limits don't truncate.
+fn cut_off<'a>(
+    s: &'a [u8],
+    limit: Limit,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> Result<(), nom::Err<ParseError<'a>>> {
+    if !options.limits.truncate {
+        return Err(nom::Err::Failure(ParseError::limit(s, limit)));
+    }
+    context.truncated = true;
+    Ok(())
+}
 
-            if !span.content.is_empty() {
-                spans.push(span);
+/// The `result` of parsing `s`, where an error is a failure at `s`: the parse can't get past
+/// it, and stopping there would silently drop the rest of the input.
+fn malformed_at_error<'a, O>(s: &'a [u8], result: IResult<'a, O>) -> IResult<'a, O> {
+    match result {
+        Err(nom::Err::Error(_)) => Err(nom::Err::Failure(ParseError::new(
+            s,
+            nom::error::ErrorKind::Verify,
+        ))),
+        result => result,
+    }
+}
+
+/// Find the next control sequence that switches to (`true`) or back from (`false`) the
+/// alternate screen, with DEC private mode 47, 1047 or 1049.
+fn alternate_screen_switch(s: &[u8], options: &ParseOptions) -> Option<(Range<usize>, bool)> {
+    let mut rest = s;
+    while !rest.is_empty() {
+        let Ok((next, token)) = token(rest, options) else {
+            break;
+        };
+        if let Token::Csi {
+            private_marker: Some(b'?'),
+            params,
+            intermediates: [],
+            final_byte: final_byte @ (b'h' | b'l'),
+        } = token
+        {
+            if params
+                .iter()
+                .any(|param| matches!(param.value, 47 | 1047 | 1049))
+            {
+                let start = s.len() - rest.len();
+                return Some((start..s.len() - next.len(), final_byte == b'h'));
             }
-            text = s;
-            if text.is_empty() {
-                break;
+        }
+        rest = next;
+    }
+    None
+}
+
+/// Keep the final frame of the alternate `screen`, which ends at `s`, as `options` ask.
+///
+/// A final frame that is put into the text ends the `open` line before it.
+fn end_alternate_screen<'a>(
+    s: &'a [u8],
+    screen: Screen,
+    open: &mut OpenLine<'a>,
+    options: &ParseOptions,
+    context: &mut Context,
+    parsed: &mut ParsedText<'a>,
+) -> Result<(), nom::Err<ParseError<'a>>> {
+    match options.alternate_screen {
+        AlternateScreen::Inline | AlternateScreen::Drop => (),
+        AlternateScreen::FinalFrame if options.virtual_screen.is_some() => {
+            context.screen.write_rows(screen);
+        }
+        AlternateScreen::FinalFrame => {
+            if !end_line(s, open, options, context, parsed)? {
+                return Ok(());
             }
+            for (spans, attributes) in screen.into_lines() {
+                if !push_line(s, Line::from(spans), attributes, options, context, parsed)? {
+                    break;
+                }
+            }
+            context.line = context.line_count(parsed);
+        }
+        AlternateScreen::Separate => {
+            let lines = screen
+                .into_lines()
+                .into_iter()
+                .map(|(spans, _)| Line::from(spans));
+            parsed.alternate_screens.push(Text::from_iter(lines));
         }
+    }
+    Ok(())
+}
 
-        Ok((s, (Line::from(spans), last)))
+/// Add the `open` line to the text, ending it at `s`.
+///
+/// Returns whether the whole line was added, like [`push_line`].
+fn end_line<'a>(
+    s: &'a [u8],
+    open: &mut OpenLine<'a>,
+    options: &ParseOptions,
+    context: &mut Context,
+    parsed: &mut ParsedText<'a>,
+) -> Result<bool, nom::Err<ParseError<'a>>> {
+    let Some((line, attributes)) = open.take() else {
+        return Ok(true);
+    };
+    let complete = push_line(s, line, attributes, options, context, parsed)?;
+    context.line = context.line_count(parsed);
+    context.line_started = false;
+    Ok(complete)
+}
+
+/// Copy the borrowed span contents so the `Text` no longer depends on the input.
+pub(crate) fn into_owned(text: Text<'_>) -> Text<'static> {
+    let lines = text.lines.into_iter().map(into_owned_line).collect();
+    Text {
+        lines,
+        style: text.style,
+        alignment: text.alignment,
     }
 }
 
-#[cfg(feature = "zero-copy")]
-fn line_fast(style: Style) -> impl Fn(&[u8]) -> IResult<&[u8], (Line<'_>, Style)> {
-    // let style_: Style = Default::default();
-    move |s: &[u8]| -> IResult<&[u8], (Line<'_>, Style)> {
-        let (s, mut text) = take_while(|c| c != b'\n' && c != b'\r').parse(s)?;
-        let (s, _) = opt(newline).parse(s)?;
-        let mut spans = Vec::new();
-        let mut last = style;
-        while let Ok((s, span)) = span_fast(last)(text) {
-            last = last.patch(span.style);
-            // If the spans is empty then it might be possible that the style changes
-            // but there is no text change
-            if !span.content.is_empty() {
+/// Copy the borrowed span contents so the `Line` no longer depends on the input.
+pub(crate) fn into_owned_line(line: Line<'_>) -> Line<'static> {
+    let spans = line
+        .spans
+        .into_iter()
+        .map(|span| Span::styled(span.content.into_owned(), span.style))
+        .collect::<Vec<_>>();
+    Line {
+        spans,
+        style: line.style,
+        alignment: line.alignment,
+    }
+}
+
+fn newline(s: &[u8]) -> IResult<'_, ()> {
+    let (s, _) = alt((tag("\r\n"), tag("\n"), tag("\r"))).parse(s)?;
+    Ok((s, ()))
+}
+
+#[allow(clippy::type_complexity)]
+fn line<'a>(
+    s: &'a [u8],
+    rendition: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> IResult<'a, (Line<'a>, Vec<SpanAttributes>, Rendition)> {
+    if options.overwrite_on_carriage_return || options.overstrike || options.tab_width.is_some() {
+        return cell_line(s, rendition, options, context);
+    }
+    let mut s = s;
+    let mut spans = Vec::new();
+    let mut attributes = Vec::new();
+    let mut last = rendition;
+    let max_spans = options.limits.max_spans_per_line;
+    // The spans are parsed up to the line break, not from a line split off before, because a
+    // control string can go on over line breaks
+    while !matches!(s.first(), None | Some(b'\n' | b'\r')) {
+        let (rest, (span, rendition)) = span(s, last, options, context)?;
+        last = rendition;
+        // If the spans is empty then it might be possible that the style changes
+        // but there is no text change
+        if !span.content.is_empty() {
+            let full = max_spans.is_some_and(|max| spans.len() >= max);
+            if full && !options.limits.truncate {
+                return Err(nom::Err::Failure(ParseError::limit(s, Limit::SpansPerLine)));
+            }
+            // The text of the rest of the line is left out, but its escape sequences still
+            // change the style and links of the lines after it
+            if !full {
                 spans.push(span);
+                context.line_started = true;
+                if !context.text_only {
+                    attributes.push(span_attributes(rendition, options, context));
+                }
+            }
+        }
+        s = rest;
+    }
+    let (s, _) = opt(newline).parse(s)?;
+
+    Ok((s, (Line::from(spans), attributes, last)))
+}
+
+/// A line where a lone `\r` or `\b` moves the cursor back, and the following text overwrites
+/// the text that is already there.
+#[allow(clippy::type_complexity)]
+fn cell_line<'a>(
+    s: &'a [u8],
+    rendition: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> IResult<'a, (Line<'a>, Vec<SpanAttributes>, Rendition)> {
+    let overwrite = options.overwrite_on_carriage_return;
+    let mut s = s;
+    let mut last = rendition;
+    loop {
+        match s {
+            [b'\r', rest @ ..] if overwrite => {
+                context.screen.carriage_return();
+                s = rest;
+                continue;
             }
-            text = s;
-            if text.is_empty() {
+            [] | [b'\n' | b'\r', ..] => break,
+            _ => (),
+        }
+        let (rest, (span, rendition)) = span(s, last, options, context)?;
+        last = rendition;
+        if !span.content.is_empty() {
+            context.line_started = true;
+            let attributes = span_attributes(rendition, options, context);
+            let budget = output_budget(options, context);
+            let written = context
+                .screen
+                .write(&span.content, span.style, attributes, budget);
+            if let Err(limit) = written {
+                cut_off(s, limit, options, context)?;
                 break;
             }
         }
+        s = rest;
+    }
+    let (s, _) = opt(newline).parse(s)?;
+    let (spans, attributes) = context.screen.take_line();
+    Ok((s, (Line::from(spans), attributes, last)))
+}
+
+/// Write `s` to the virtual screen, returning the part that couldn't be parsed and the
+/// rendition at the end.
+///
+/// `\n` moves to the start of the next row, like a terminal that translates it to `\r\n`.
+fn screen<'a>(
+    mut s: &'a [u8],
+    mut last: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> IResult<'a, Rendition> {
+    while !s.is_empty() {
+        context.line = context.screen.row();
+        context.line_started = context.screen.column() > 0;
+        match s[0] {
+            b'\n' => context.screen.new_line(),
+            b'\r' => context.screen.carriage_return(),
+            _ => {
+                let (rest, (span, rendition)) =
+                    malformed_at_error(s, span(s, last, options, context))?;
+                last = rendition;
+                if !span.content.is_empty() {
+                    // The cells are allocated before the text gets to the output, so the limits
+                    // are checked as they are written
+                    let attributes = span_attributes(rendition, options, context);
+                    let budget = output_budget(options, context);
+                    let written =
+                        context
+                            .screen
+                            .write(&span.content, span.style, attributes, budget);
+                    if let Err(limit) = written {
+                        cut_off(s, limit, options, context)?;
+                        break;
+                    }
+                }
+                s = rest;
+                continue;
+            }
+        }
+        s = &s[1..];
+    }
+    Ok((s, last))
+}
 
-        Ok((s, (Line::from(spans), last)))
+/// What is left of [`Limits::max_output_len`](crate::Limits::max_output_len) for the text on
+/// the screen.
+fn output_budget(options: &ParseOptions, context: &Context) -> Option<usize> {
+    let max = options.limits.max_output_len?;
+    Some(max.saturating_sub(context.output_len))
+}
+
+/// The attributes of a span written with `rendition`.
+fn span_attributes(
+    rendition: Rendition,
+    options: &ParseOptions,
+    context: &Context,
+) -> SpanAttributes {
+    let mut attributes = rendition.attributes();
+    if options.track_palette {
+        attributes.underline_color = context
+            .palette
+            .resolve_underline(attributes.underline_color);
     }
+    attributes
 }
 
-// fn span(s: &[u8]) -> IResult<&[u8], ratatui::text::Span> {
-fn span(last: Style) -> impl Fn(&[u8]) -> IResult<&[u8], Span<'static>, nom::error::Error<&[u8]>> {
-    move |s: &[u8]| -> IResult<&[u8], Span<'static>> {
-        let mut last = last;
-        let (s, style) = opt(style(last)).parse(s)?;
+fn span<'a>(
+    s: &'a [u8],
+    last: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> IResult<'a, (Span<'a>, Rendition)> {
+    let mut last = last;
+    let s = context.charsets.shift(s);
+    let c1_controls = options.c1_controls;
+    // Most spans start with an escape sequence or at the start of a line, so text doesn't go
+    // through the escape sequence parsers to find that it isn't one
+    let (s, rendition) = match s.first() {
+        Some(&c) if c == b'\x1b' || (c1_controls && is_c1(c)) => {
+            opt(|s| style(s, last, options, context)).parse(s)?
+        }
+        _ => (s, None),
+    };
 
-        #[cfg(feature = "simd")]
-        let (s, text) = map_res(
-            take_while(|c| c != b'\x1b' && c != b'\n' && c != b'\r'),
-            |t| simdutf8::basic::from_utf8(t),
-        )
-        .parse(s)?;
+    let end = find_control(s, options, |c| {
+        matches!(c, b'\x1b' | b'\n' | b'\r' | b'\x0e' | b'\x0f') || (c1_controls && is_c1(c))
+    });
 
-        #[cfg(not(feature = "simd"))]
-        let (s, text) = map_res(
-            take_while(|c| c != b'\x1b' && c != b'\n' && c != b'\r'),
-            |t| std::str::from_utf8(t),
-        )
-        .parse(s)?;
+    let (rest, bytes) = take(end).parse(s)?;
 
-        if let Some(style) = style.flatten() {
-            last = last.patch(style);
+    #[cfg(feature = "simd")]
+    let text = simdutf8::basic::from_utf8(bytes).ok();
+
+    #[cfg(not(feature = "simd"))]
+    let text = std::str::from_utf8(bytes).ok();
+
+    let text = match text {
+        _ if options.encoding != Encoding::Utf8 => charset::decode(bytes, options.encoding),
+        Some(text) => Cow::Borrowed(text),
+        None if options.lossy_utf8 => {
+            for chunk in bytes.utf8_chunks() {
+                if !chunk.invalid().is_empty() {
+                    context.diagnostic(chunk.invalid(), Reason::InvalidUtf8);
+                }
+            }
+            String::from_utf8_lossy(bytes)
         }
+        // Error::from_parse finds the invalid bytes from here
+        None => return Err(nom::Err::Failure(ParseError::utf8(s))),
+    };
+    let s = rest;
 
-        Ok((s, Span::styled(text.to_owned(), last)))
+    if let Some(rendition) = rendition.flatten() {
+        last = Rendition {
+            style: last.style.patch(rendition.style),
+            ..rendition
+        };
     }
+
+    let style = if options.track_palette {
+        context.palette.resolve(last.style)
+    } else {
+        last.style
+    };
+    Ok((
+        s,
+        (Span::styled(context.charsets.translate(text), style), last),
+    ))
 }
 
-#[cfg(feature = "zero-copy")]
-fn span_fast(last: Style) -> impl Fn(&[u8]) -> IResult<&[u8], Span<'_>, nom::error::Error<&[u8]>> {
-    move |s: &[u8]| -> IResult<&[u8], Span<'_>> {
-        let mut last = last;
-        let (s, style) = opt(style(last)).parse(s)?;
+fn style<'a>(
+    s: &'a [u8],
+    rendition: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> IResult<'a, Option<Rendition>> {
+    // SGR sequences are by far the most common, so they skip the allocations of a `Token`, and
+    // without sub-parameters their parameters are read as they are found
+    if let Some((rest, values)) = sgr_sequence(s, options) {
+        let sequence = &s[..s.len() - rest.len()];
+        let rendition = sgr(sequence, &values, rendition, options, context)?;
+        return Ok((rest, Some(rendition)));
+    }
+    let (rest, token) = escape_sequence(s, options)?;
+    let sequence = &s[..s.len() - rest.len()];
+    let malformed = rejected(&token);
+    if let Some(reason) = malformed {
+        if options.strict {
+            return Err(nom::Err::Failure(ParseError::rejected(s, reason)));
+        }
+        context.diagnostic(sequence, reason);
+    }
+    let s = rest;
+    match token {
+        Token::Sgr(params) => {
+            let rendition = sgr(sequence, &params, rendition, options, context)?;
+            Ok((s, Some(rendition)))
+        }
+        Token::Osc(payload) => {
+            let rendition = osc(payload, rendition, options, context).unwrap_or_else(|reason| {
+                context.diagnostic(sequence, reason);
+                None
+            });
+            Ok((s, rendition))
+        }
+        // HTS
+        Token::Escape(b"\x1bH" | b"\x88") => {
+            context.screen.set_tab_stop();
+            Ok((s, None))
+        }
+        Token::Escape(escape) => {
+            if !context.charsets.designate(escape) && malformed.is_none() {
+                context.diagnostic(sequence, Reason::UnsupportedEscape);
+            }
+            Ok((s, None))
+        }
+        Token::Csi {
+            private_marker,
+            params,
+            intermediates,
+            final_byte,
+        } => {
+            let applied = private_marker.is_none()
+                && intermediates.is_empty()
+                && context.screen.control_sequence(final_byte, &params);
+            if !applied {
+                context.diagnostic(sequence, Reason::UnsupportedCsi(final_byte));
+            }
+            Ok((s, None))
+        }
+        Token::ControlString { kind, payload } => {
+            match &options.control_string_handler {
+                Some(handler) => handler.call(kind, payload),
+                None => context.diagnostic(sequence, Reason::UnsupportedControlString),
+            }
+            Ok((s, None))
+        }
+        _ => Ok((s, None)),
+    }
+}
 
-        #[cfg(feature = "simd")]
-        let (s, text) = map_res(
-            take_while(|c| c != b'\x1b' && c != b'\n' && c != b'\r'),
-            |t| simdutf8::basic::from_utf8(t),
-        )
-        .parse(s)?;
+/// An SGR sequence without a private marker, intermediate bytes or sub-parameters, returning the
+/// rest of `s` and the parameters.
+fn sgr_sequence<'a>(
+    s: &'a [u8],
+    options: &ParseOptions,
+) -> Option<(&'a [u8], smallvec::SmallVec<[u16; 16]>)> {
+    let (body, _) = introducer(s, b'[', options).ok()?;
+    let mut values = smallvec::SmallVec::new();
+    let mut value = 0u16;
+    for (len, &c) in body.iter().enumerate() {
+        match c {
+            b'0'..=b'9' => value = value.saturating_mul(10).saturating_add(u16::from(c - b'0')),
+            b';' => values.push(std::mem::take(&mut value)),
+            b'm' if len <= options.limits.max_escape_len => {
+                if len > 0 {
+                    values.push(value);
+                }
+                return Some((&body[len + 1..], values));
+            }
+            _ => return None,
+        }
+    }
+    None
+}
 
-        #[cfg(not(feature = "simd"))]
-        let (s, text) = map_res(
-            take_while(|c| c != b'\x1b' && c != b'\n' && c != b'\r'),
-            |t| std::str::from_utf8(t),
-        )
-        .parse(s)?;
+/// Apply the SGR `sequence` with `params` to `rendition`.
+fn sgr<'a>(
+    sequence: &'a [u8],
+    params: &[impl SgrParam],
+    rendition: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> Result<Rendition, nom::Err<ParseError<'a>>> {
+    let (items, malformed) = sgr_items(params);
+    if let Some(reason) = malformed {
+        if options.strict {
+            return Err(nom::Err::Failure(ParseError::rejected(sequence, reason)));
+        }
+        context.diagnostic(sequence, reason);
+    }
+    Ok(Rendition {
+        hyperlink: rendition.hyperlink,
+        ..Rendition::from(AnsiStates {
+            items,
+            style: rendition.style,
+            underline_style: rendition.underline_style,
+            underline_color: rendition.underline_color,
+        })
+    })
+}
+
+/// What is wrong with `token` in strict mode, if anything.
+fn rejected(token: &Token<'_>) -> Option<Reason> {
+    match *token {
+        Token::Escape(raw) => {
+            let kind = match *raw {
+                [b'\x1b'] => return Some(Reason::UnterminatedEscape),
+                [b'\x1b', kind, ..] => kind,
+                [c, ..] if is_c1(c) => c - 0x40,
+                _ => return None,
+            };
+            match kind {
+                b'[' if raw.last().is_some_and(|&c| is_final(c)) => Some(Reason::MalformedCsi),
+                b'[' => Some(Reason::UnterminatedCsi),
+                b']' => Some(Reason::UnterminatedOsc),
+                b'P' | b'_' | b'^' | b'X' => Some(Reason::UnterminatedControlString),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
 
-        if let Some(style) = style.flatten() {
-            last = last.patch(style);
+/// Apply an operating system command, returning the new rendition if it changed, or why it
+/// wasn't applied.
+fn osc(
+    payload: &[u8],
+    rendition: Rendition,
+    options: &ParseOptions,
+    context: &mut Context,
+) -> Result<Option<Rendition>, Reason> {
+    let (command, args) = match payload.iter().position(|&c| c == b';') {
+        Some(i) => (&payload[..i], &payload[i + 1..]),
+        None => (payload, &[][..]),
+    };
+    match command {
+        b"0" | b"1" | b"2" => {
+            let kind = match command {
+                b"0" => TitleKind::IconAndWindow,
+                b"1" => TitleKind::Icon,
+                _ => TitleKind::Window,
+            };
+            context.titles.push(Title {
+                kind,
+                title: String::from_utf8_lossy(args).into_owned(),
+                line: context.line,
+            });
+            Ok(None)
+        }
+        b"8" => {
+            // Only the span attributes refer to the hyperlinks
+            let hyperlink = if context.text_only {
+                None
+            } else {
+                hyperlink(args).map(|hyperlink| context.hyperlink(hyperlink))
+            };
+            Ok(Some(Rendition {
+                hyperlink,
+                ..rendition
+            }))
+        }
+        b"133" => {
+            context.semantic_prompt(args);
+            Ok(None)
+        }
+        b"4" | b"10" | b"11" | b"104" | b"110" | b"111" if options.track_palette => {
+            context.palette.apply(command, args);
+            Ok(None)
         }
+        _ => Err(Reason::UnsupportedOsc(
+            std::str::from_utf8(command)
+                .ok()
+                .and_then(|n| n.parse().ok()),
+        )),
+    }
+}
 
-        Ok((s, Span::styled(text, last)))
+/// The arguments of an OSC 8 hyperlink: `params;uri`, where `params` are `:` separated
+/// `key=value` pairs. An empty URI ends the hyperlink.
+fn hyperlink(args: &[u8]) -> Option<Hyperlink> {
+    let i = args.iter().position(|&c| c == b';')?;
+    let (params, uri) = (&args[..i], &args[i + 1..]);
+    if uri.is_empty() {
+        return None;
     }
+    let id = params
+        .split(|&c| c == b':')
+        .find_map(|param| param.strip_prefix(b"id="))
+        .map(|id| String::from_utf8_lossy(id).into_owned());
+    Some(Hyperlink {
+        uri: String::from_utf8_lossy(uri).into_owned(),
+        id,
+    })
 }
 
-#[allow(clippy::type_complexity)]
-fn style(
-    style: Style,
-) -> impl Fn(&[u8]) -> IResult<&[u8], Option<Style>, nom::error::Error<&[u8]>> {
-    move |s: &[u8]| -> IResult<&[u8], Option<Style>> {
-        let (s, r) = match opt(ansi_sgr_code).parse(s)? {
-            (s, Some(r)) => (s, Some(r)),
-            (s, None) => {
-                let (s, _) = any_escape_sequence(s)?;
-                (s, None)
+/// The next token of `s`, see [`Tokens`](crate::Tokens).
+pub(crate) fn token<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Token<'a>> {
+    match s.first() {
+        Some(b'\n') => Ok((&s[1..], Token::Newline)),
+        Some(b'\r') if s.get(1) == Some(&b'\n') => Ok((&s[2..], Token::Newline)),
+        Some(&c) if c == b'\x1b' || (options.c1_controls && is_c1(c)) => {
+            escape_sequence(s, options)
+        }
+        Some(&c) if is_control(c) => Ok((&s[1..], Token::Control(c))),
+        _ => text_token(s, options),
+    }
+}
+
+fn is_control(c: u8) -> bool {
+    c < 0x20 || c == 0x7f
+}
+
+fn is_c1(c: u8) -> bool {
+    (0x80..=0x9f).contains(&c)
+}
+
+/// The index of the first `\n` in `s`, or `\r` if `carriage_return`, that isn't part of an
+/// escape sequence.
+///
+/// The escape sequences are skipped as the parser reads them, so a control string such as a
+/// sixel image goes on over line breaks up to its string terminator.
+pub(crate) fn find_line_break(
+    s: &[u8],
+    carriage_return: bool,
+    options: &ParseOptions,
+) -> Option<usize> {
+    let c1_controls = options.c1_controls;
+    let mut i = 0;
+    loop {
+        i += find_control(&s[i..], options, |c| {
+            matches!(c, b'\n' | b'\x1b')
+                || (carriage_return && c == b'\r')
+                || (c1_controls && is_c1(c))
+        });
+        if matches!(s.get(i)?, b'\n' | b'\r') {
+            return Some(i);
+        }
+        i += match escape_sequence(&s[i..], options) {
+            Ok((rest, _)) => s.len() - i - rest.len(),
+            // Past the limit, where the parse fails too
+            Err(_) => {
+                let introducer_len = if s[i] == b'\x1b' { 2 } else { 1 };
+                (s.len() - i).min(introducer_len + options.limits.max_escape_len)
             }
         };
-        Ok((s, r.map(|r| Style::from(AnsiStates { style, items: r }))))
     }
 }
 
-/// A complete ANSI SGR code
-fn ansi_sgr_code(
-    s: &[u8],
-) -> IResult<&[u8], smallvec::SmallVec<[AnsiItem; 2]>, nom::error::Error<&[u8]>> {
-    delimited(
-        tag("\x1b["),
-        fold_many0(ansi_sgr_item, smallvec::SmallVec::new, |mut items, item| {
-            items.push(item);
-            items
-        }),
-        char('m'),
-    )
-    .parse(s)
-}
-
-fn any_escape_sequence(s: &[u8]) -> IResult<&[u8], Option<&[u8]>> {
-    // Attempt to consume most escape codes, including a single escape char.
-    //
-    // Most escape codes begin with ESC[ and are terminated by an alphabetic character,
-    // but OSC codes begin with ESC] and are terminated by an ascii bell (\x07)
-    // and a truncated/invalid code may just be a standalone ESC or not be terminated.
-    //
-    // We should try to consume as much of it as possible to match behavior of most terminals;
-    // where we fail at that we should at least consume the escape char to avoid infinitely looping
-
-    let (input, garbage) = preceded(
-        char('\x1b'),
-        opt(alt((
-            delimited(char('['), take_till(AsChar::is_alpha), opt(take(1u8))),
-            delimited(char(']'), take_till(|c| c == b'\x07'), opt(take(1u8))),
+/// The index of the first byte in `s` that `stop` matches.
+///
+/// With `c1_controls` set and UTF-8 input, bytes that continue a UTF-8 encoded character are
+/// skipped, because the continuation bytes `0x80..=0x9F` look like C1 controls.
+fn find_control(s: &[u8], options: &ParseOptions, stop: impl Fn(u8) -> bool) -> usize {
+    if !options.c1_controls || options.encoding != Encoding::Utf8 {
+        return s.iter().position(|&c| stop(c)).unwrap_or(s.len());
+    }
+    let mut continuation = 0;
+    for (i, &c) in s.iter().enumerate() {
+        if continuation > 0 && (0x80..=0xbf).contains(&c) {
+            continuation -= 1;
+            continue;
+        }
+        continuation = match c {
+            0xc2..=0xdf => 1,
+            0xe0..=0xef => 2,
+            0xf0..=0xf4 => 3,
+            _ => 0,
+        };
+        if stop(c) {
+            return i;
+        }
+    }
+    s.len()
+}
+
+/// A run of printable text, or the invalid UTF-8 at its start.
+fn text_token<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Token<'a>> {
+    let c1_controls = options.c1_controls;
+    let end = find_control(s, options, |c| is_control(c) || (c1_controls && is_c1(c)));
+    let (rest, run) = take(end).parse(s)?;
+    if run.is_empty() {
+        return Err(nom::Err::Error(ParseError::new(
+            s,
+            nom::error::ErrorKind::TakeWhile1,
+        )));
+    }
+    match std::str::from_utf8(run) {
+        Ok(text) => Ok((rest, Token::Text(text))),
+        Err(e) if e.valid_up_to() > 0 => {
+            let (valid, rest) = s.split_at(e.valid_up_to());
+            let text = std::str::from_utf8(valid).expect("validated above");
+            Ok((rest, Token::Text(text)))
+        }
+        Err(e) => {
+            let (invalid, rest) = s.split_at(e.error_len().unwrap_or(run.len()));
+            Ok((rest, Token::Invalid(invalid)))
+        }
+    }
+}
+
+/// Any escape sequence, starting at the `ESC` (or the C1 control, if enabled).
+///
+/// Never fails on input starting with `ESC`: malformed or truncated sequences are consumed as
+/// [`Token::Escape`], at least the `ESC` itself, so callers can't loop forever.
+pub(crate) fn escape_sequence<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Token<'a>> {
+    let kind = match *s {
+        [b'\x1b', kind, ..] => Some(kind),
+        [c, ..] if options.c1_controls && is_c1(c) => Some(c - 0x40),
+        [b'\x1b'] => None,
+        _ => {
+            return Err(nom::Err::Error(ParseError::new(
+                s,
+                nom::error::ErrorKind::Char,
+            )));
+        }
+    };
+    match kind {
+        Some(b'[') => control_sequence(s, options),
+        Some(b']') => operating_system_command(s, options),
+        Some(kind @ (b'P' | b'_' | b'^' | b'X')) => control_string(s, kind, options),
+        _ if s[0] != b'\x1b' => Ok((&s[1..], Token::Escape(&s[..1]))),
+        _ => escape(s),
+    }
+}
+
+/// The introducer of an escape sequence: `ESC` followed by `kind`, or the equivalent C1 control
+/// if enabled.
+fn introducer<'a>(s: &'a [u8], kind: u8, options: &ParseOptions) -> IResult<'a, &'a [u8]> {
+    match *s {
+        [b'\x1b', k, ..] if k == kind => Ok((&s[2..], &s[..2])),
+        [c, ..] if options.c1_controls && c == kind + 0x40 => Ok((&s[1..], &s[..1])),
+        _ => Err(nom::Err::Error(ParseError::new(
+            s,
+            nom::error::ErrorKind::Tag,
         ))),
-    )
-    .parse(s)?;
-    Ok((input, garbage))
+    }
 }
 
-/// An ANSI SGR attribute
-fn ansi_sgr_item(s: &[u8]) -> IResult<&[u8], AnsiItem> {
-    let (s, c) = u8(s)?;
-    let code = AnsiCode::from(c);
-    let (s, color) = match code {
-        AnsiCode::SetForegroundColor | AnsiCode::SetBackgroundColor => {
-            let (s, _) = opt(tag(";")).parse(s)?;
-            let (s, color) = color(s)?;
-            (s, Some(color))
+fn is_intermediate(c: u8) -> bool {
+    (0x20..=0x2f).contains(&c)
+}
+
+fn is_final(c: u8) -> bool {
+    (0x40..=0x7e).contains(&c)
+}
+
+/// A control sequence: `ESC [`, an optional private marker, the parameters, intermediate bytes
+/// and a final byte.
+fn control_sequence<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Token<'a>> {
+    // Parameters longer than the limit aren't collected, the sequence is skipped as malformed
+    let (body, _) = introducer(s, b'[', options)?;
+    let params_len = body
+        .iter()
+        .position(|&c| !(c.is_ascii_digit() || b"<=>?;:".contains(&c)))
+        .unwrap_or(body.len());
+    let too_long = params_len > options.limits.max_escape_len;
+    if too_long && !options.limits.truncate {
+        return Err(nom::Err::Failure(ParseError::limit(s, Limit::EscapeLen)));
+    }
+    let sequence = if too_long {
+        Err(nom::Err::Error(ParseError::limit(s, Limit::EscapeLen)))
+    } else {
+        (
+            |s| introducer(s, b'[', options),
+            opt(verify(take(1u8), |m: &[u8]| b"<=>?".contains(&m[0]))),
+            params,
+            take_while(is_intermediate),
+            verify(take(1u8), |f: &[u8]| is_final(f[0])),
+        )
+            .parse(s)
+    };
+    match sequence {
+        Ok((rest, (_, private_marker, params, intermediates, final_byte))) => {
+            let private_marker = private_marker.map(|m| m[0]);
+            let final_byte = final_byte[0];
+            let token =
+                if final_byte == b'm' && private_marker.is_none() && intermediates.is_empty() {
+                    Token::Sgr(params)
+                } else {
+                    Token::Csi {
+                        private_marker,
+                        params,
+                        intermediates,
+                        final_byte,
+                    }
+                };
+            Ok((rest, token))
+        }
+        Err(nom::Err::Error(_)) => {
+            // A malformed or truncated control sequence. We should try to consume as much of it
+            // as possible to match behavior of most terminals, which skip to the final byte.
+            let (rest, raw) = recognize((
+                |s| introducer(s, b'[', options),
+                take_till(|c| is_final(c) || c == b'\x1b' || c == b'\n' || c == b'\r'),
+                opt(verify(take(1u8), |f: &[u8]| is_final(f[0]))),
+            ))
+            .parse(s)?;
+            Ok((rest, Token::Escape(raw)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// An operating system command, `ESC ]` terminated by an ascii bell (`\x07`) or the string
+/// terminator (`ESC \`, or the C1 `0x9C` if enabled).
+///
+/// An OSC that isn't terminated before a newline, another escape sequence or
+/// [`Limits::max_escape_len`](crate::Limits::max_escape_len) bytes is malformed and consumed up to that point as a
+/// [`Token::Escape`]. Unless the limits truncate, going over the limit fails instead.
+fn operating_system_command<'a>(s: &'a [u8], options: &ParseOptions) -> IResult<'a, Token<'a>> {
+    let (body, _) = introducer(s, b']', options)?;
+    let c1_controls = options.c1_controls;
+    let max = options.limits.max_escape_len;
+    let window = &body[..body.len().min(max)];
+    let end = find_control(window, options, |c| {
+        matches!(c, b'\x07' | b'\x1b' | b'\n' | b'\r') || (c1_controls && c == 0x9c)
+    });
+    let (payload, rest) = body.split_at(end);
+    let terminator_len = match *rest {
+        [b'\x07', ..] => 1,
+        [b'\x1b', b'\\', ..] => 2,
+        [0x9c, ..] if c1_controls => 1,
+        _ if end >= max && !options.limits.truncate => {
+            return Err(nom::Err::Failure(ParseError::limit(s, Limit::EscapeLen)));
+        }
+        _ => {
+            let (raw, rest) = s.split_at(s.len() - rest.len());
+            return Ok((rest, Token::Escape(raw)));
         }
-        _ => (s, None),
     };
-    let (s, _) = opt(tag(";")).parse(s)?;
-    Ok((s, AnsiItem { code, color }))
+    Ok((&rest[terminator_len..], Token::Osc(payload)))
 }
 
-fn color(s: &[u8]) -> IResult<&[u8], Color> {
-    let (s, c_type) = color_type(s)?;
-    let (s, _) = opt(tag(";")).parse(s)?;
-    match c_type {
-        ColorType::TrueColor => {
-            let (s, (r, _, g, _, b)) = (u8, tag(";"), u8, tag(";"), u8).parse(s)?;
-            Ok((s, Color::Rgb(r, g, b)))
+/// A DCS (`ESC P`), APC (`ESC _`), PM (`ESC ^`) or SOS (`ESC X`) string terminated by the string
+/// terminator (`ESC \`, or the C1 `0x9C` if enabled).
+///
+/// A doubled `ESC` inside the string is skipped, as used by the tmux passthrough. Unlike an OSC,
+/// the string goes on over line breaks, as in multi-line sixel images. A string that isn't
+/// terminated before another escape sequence is malformed and consumed up to that point as a
+/// [`Token::Escape`], and so is a string longer than
+/// [`Limits::max_escape_len`](crate::Limits::max_escape_len) if the limits truncate.
+fn control_string<'a>(s: &'a [u8], kind: u8, options: &ParseOptions) -> IResult<'a, Token<'a>> {
+    let (body, _) = introducer(s, kind, options)?;
+    let kind = match kind {
+        b'P' => ControlStringKind::Dcs,
+        b'_' => ControlStringKind::Apc,
+        b'^' => ControlStringKind::Pm,
+        _ => ControlStringKind::Sos,
+    };
+    let c1_controls = options.c1_controls;
+    let max = options.limits.max_escape_len;
+    let window_len = body.len().min(max);
+    let mut end = 0;
+    let terminator_len = loop {
+        end += find_control(&body[end..window_len], options, |c| {
+            c == b'\x1b' || (c1_controls && c == 0x9c)
+        });
+        match body[end..] {
+            [b'\x1b', b'\x1b', ..] if end + 2 <= window_len => end += 2,
+            [b'\x1b', b'\\', ..] => break 2,
+            [0x9c, ..] if c1_controls => break 1,
+            _ if end >= max && !options.limits.truncate => {
+                return Err(nom::Err::Failure(ParseError::limit(s, Limit::EscapeLen)));
+            }
+            _ => {
+                let (raw, rest) = s.split_at(s.len() - body.len() + end);
+                return Ok((rest, Token::Escape(raw)));
+            }
         }
-        ColorType::EightBit => {
-            let (s, index) = u8(s)?;
-            Ok((s, Color::Indexed(index)))
+    };
+    let (payload, rest) = body.split_at(end);
+    Ok((
+        &rest[terminator_len..],
+        Token::ControlString { kind, payload },
+    ))
+}
+
+/// Any other escape sequence: `ESC`, intermediate bytes and a final byte, e.g. `ESC 7` or
+/// `ESC ( B`. A standalone `ESC` is consumed on its own.
+fn escape(s: &[u8]) -> IResult<'_, Token<'_>> {
+    let (rest, raw) = recognize((
+        char('\x1b'),
+        opt((
+            take_while(is_intermediate),
+            verify(take(1u8), |f: &[u8]| (0x30..=0x7e).contains(&f[0])),
+        )),
+    ))
+    .parse(s)?;
+    Ok((rest, Token::Escape(raw)))
+}
+
+/// The parameters of a control sequence, e.g. `1;38:2::255:0:0` or `;4`.
+///
+/// Numbers may have any width, including leading zeros.
+fn params(s: &[u8]) -> IResult<'_, Vec<Param>> {
+    let (s, raw) = take_while(|c: u8| c.is_ascii_digit() || c == b';' || c == b':').parse(s)?;
+    if raw.is_empty() {
+        return Ok((s, Vec::new()));
+    }
+    let params = raw
+        .split(|&c| c == b';')
+        .map(|param| {
+            let mut values = param.split(|&c| c == b':').map(param_value);
+            Param {
+                value: values.next().unwrap_or_default(),
+                subparams: values.collect(),
+            }
+        })
+        .collect();
+    Ok((s, params))
+}
+
+fn param_value(digits: &[u8]) -> u16 {
+    digits.iter().fold(0u16, |value, digit| {
+        value
+            .saturating_mul(10)
+            .saturating_add(u16::from(digit - b'0'))
+    })
+}
+
+/// A parameter as [`sgr_items`] sees it: a [`Param`], or just a number if the sequence has no
+/// sub-parameters.
+trait SgrParam {
+    fn value(&self) -> u16;
+    fn subparams(&self) -> &[u16];
+}
+
+impl SgrParam for Param {
+    fn value(&self) -> u16 {
+        self.value
+    }
+
+    fn subparams(&self) -> &[u16] {
+        &self.subparams
+    }
+}
+
+impl SgrParam for u16 {
+    fn value(&self) -> u16 {
+        *self
+    }
+
+    fn subparams(&self) -> &[u16] {
+        &[]
+    }
+}
+
+/// The SGR attributes of a list of parameters, and the first problem with them, if any.
+///
+/// Every parameter turns into one item, the arguments of extended colors are consumed by the
+/// color code. Out of range values don't invalidate the rest of the sequence.
+fn sgr_items(params: &[impl SgrParam]) -> (smallvec::SmallVec<[AnsiItem; 2]>, Option<Reason>) {
+    let mut items = smallvec::SmallVec::new();
+    let mut problem = None;
+    let mut rest = params;
+    while let Some((param, tail)) = rest.split_first() {
+        rest = tail;
+        let mut code = AnsiCode::from(param.value());
+        let mut color = None;
+        let mut reason = None;
+        match code {
+            AnsiCode::Underline => {
+                if let Some(&sub) = param.subparams().first() {
+                    code = u8::try_from(sub)
+                        .ok()
+                        .and_then(UnderlineStyle::from_sgr)
+                        .map_or_else(
+                            || {
+                                reason = Some(Reason::UnknownUnderlineStyle(sub));
+                                AnsiCode::Code(vec![param.value(), sub])
+                            },
+                            AnsiCode::UnderlineStyle,
+                        );
+                }
+            }
+            AnsiCode::SetForegroundColor
+            | AnsiCode::SetBackgroundColor
+            | AnsiCode::SetUnderlineColor => {
+                let result = if param.subparams().is_empty() {
+                    let (result, consumed) = self::color(rest);
+                    rest = &rest[consumed..];
+                    result
+                } else {
+                    colon_color(param.subparams())
+                };
+                (color, reason) = match result {
+                    Ok(color) => (Some(color), None),
+                    Err(reason) => (None, Some(reason)),
+                };
+            }
+            // Fonts, Fraktur and double underline have no equivalent in a ratatui `Style`
+            AnsiCode::Code(_)
+            | AnsiCode::PrimaryFont
+            | AnsiCode::AlternateFont
+            | AnsiCode::Fraktur
+            | AnsiCode::BoldOff => reason = Some(Reason::UnknownSgr(param.value())),
+            _ => (),
         }
+        problem = problem.or(reason);
+        items.push(AnsiItem { code, color });
+    }
+    (items, problem)
+}
+
+/// The color following a `38`, `48` or `58` in the semicolon form, e.g. `5;208` or `2;255;0;0`.
+///
+/// Returns the color, or why it is invalid, and the number of parameters it used.
+fn color(params: &[impl SgrParam]) -> (Result<Color, Reason>, usize) {
+    let Some(c_type) = params.first() else {
+        return (Err(Reason::TruncatedColor), 0);
+    };
+    match color_type(c_type.value()) {
+        Some(ColorType::TrueColor) => match params.get(1..4) {
+            Some([r, g, b]) => (rgb(r.value(), g.value(), b.value()), 4),
+            _ => (Err(Reason::TruncatedColor), params.len()),
+        },
+        Some(ColorType::EightBit) => match params.get(1) {
+            Some(index) => (indexed(index.value()), 2),
+            None => (Err(Reason::TruncatedColor), 1),
+        },
+        None => (Err(Reason::InvalidColorType(c_type.value())), 1),
     }
 }
 
-fn color_type(s: &[u8]) -> IResult<&[u8], ColorType> {
-    let (s, t) = i64(s)?;
-    // NOTE: This isn't opt because a color type must always be followed by a color
-    // let (s, _) = opt(tag(";")).parse(s)?;
-    let (s, _) = tag(";").parse(s)?;
+/// A color in the ITU T.416 colon form, e.g. `5:208`, `2::255:0:0` or `2:255:0:0`.
+///
+/// The truecolor form has an optional (and possibly empty) color space id before the RGB
+/// components.
+fn colon_color(subparams: &[u16]) -> Result<Color, Reason> {
+    let (&c_type, args) = subparams.split_first().ok_or(Reason::TruncatedColor)?;
+    match color_type(c_type).ok_or(Reason::InvalidColorType(c_type))? {
+        ColorType::TrueColor => match *args {
+            [r, g, b] | [_, r, g, b, ..] => rgb(r, g, b),
+            _ => Err(Reason::TruncatedColor),
+        },
+        ColorType::EightBit => indexed(*args.first().ok_or(Reason::TruncatedColor)?),
+    }
+}
+
+fn color_type(t: u16) -> Option<ColorType> {
     match t {
-        2 => Ok((s, ColorType::TrueColor)),
-        5 => Ok((s, ColorType::EightBit)),
-        _ => Err(nom::Err::Error(nom::error::Error::new(
-            s,
-            nom::error::ErrorKind::Alt,
-        ))),
+        2 => Some(ColorType::TrueColor),
+        5 => Some(ColorType::EightBit),
+        _ => None,
     }
 }
 
+fn rgb(r: u16, g: u16, b: u16) -> Result<Color, Reason> {
+    let component = |c: u16| u8::try_from(c).map_err(|_| Reason::ColorOutOfRange);
+    Ok(Color::Rgb(component(r)?, component(g)?, component(b)?))
+}
+
+fn indexed(index: u16) -> Result<Color, Reason> {
+    u8::try_from(index)
+        .map(Color::Indexed)
+        .map_err(|_| Reason::ColorOutOfRange)
+}
+
+#[test]
+fn params_test() {
+    let (_, p) = params(b"1;38:2::255:0:0;;0004").unwrap();
+    assert_eq!(
+        p,
+        [
+            Param::new(1),
+            Param::with_subparams(38, [2, 0, 255, 0, 0]),
+            Param::default(),
+            Param::new(4),
+        ]
+    );
+    let (_, p) = params(b"99999999").unwrap();
+    assert_eq!(p[0].value, u16::MAX);
+    assert_eq!(params(b"m").unwrap().1, []);
+}
+
 #[test]
 fn color_test() {
-    let c = color(b"2;255;255;255").unwrap();
-    assert_eq!(c.1, Color::Rgb(255, 255, 255));
-    let c = color(b"5;255").unwrap();
-    assert_eq!(c.1, Color::Indexed(255));
-    let err = color(b"10;255");
-    assert_ne!(err, Ok(c));
+    let c = color(&params(b"2;255;255;255").unwrap().1);
+    assert_eq!(c, (Ok(Color::Rgb(255, 255, 255)), 4));
+    let c = color(&params(b"5;255").unwrap().1);
+    assert_eq!(c, (Ok(Color::Indexed(255)), 2));
+    let c = color(&params(b"5;256").unwrap().1);
+    assert_eq!(c, (Err(Reason::ColorOutOfRange), 2));
+    let c = color(&params(b"10;255").unwrap().1);
+    assert_eq!(c, (Err(Reason::InvalidColorType(10)), 1));
+    let c = color(&params(b"2;255;0").unwrap().1);
+    assert_eq!(c, (Err(Reason::TruncatedColor), 3));
+}
+
+#[test]
+fn colon_color_test() {
+    let c = colon_color(&[2, 0, 255, 0, 128]);
+    assert_eq!(c, Ok(Color::Rgb(255, 0, 128)));
+    let c = colon_color(&[2, 1, 255, 0, 128]);
+    assert_eq!(c, Ok(Color::Rgb(255, 0, 128)));
+    let c = colon_color(&[2, 255, 0, 128]);
+    assert_eq!(c, Ok(Color::Rgb(255, 0, 128)));
+    let c = colon_color(&[2, 0, 0, 0]);
+    assert_eq!(c, Ok(Color::Rgb(0, 0, 0)));
+    let c = colon_color(&[5, 208]);
+    assert_eq!(c, Ok(Color::Indexed(208)));
+    assert_eq!(colon_color(&[2, 255, 0]), Err(Reason::TruncatedColor));
+    assert_eq!(colon_color(&[10, 255]), Err(Reason::InvalidColorType(10)));
 }
 
 #[test]
 fn ansi_items_test() {
-    let sc = Default::default();
-    let t = style(sc)(b"\x1b[38;2;3;3;3m").unwrap().1.unwrap();
+    let sc = Rendition::default();
+    let options = ParseOptions::default();
+    let mut context = Context::default();
+    let t = style(b"\x1b[38;2;3;3;3m", sc, &options, &mut context)
+        .unwrap()
+        .1
+        .unwrap();
     assert_eq!(
         t,
-        Style::from(AnsiStates {
-            style: sc,
+        Rendition::from(AnsiStates {
+            style: sc.style,
+            underline_style: sc.underline_style,
+            underline_color: sc.underline_color,
             items: vec![AnsiItem {
                 code: AnsiCode::SetForegroundColor,
                 color: Some(Color::Rgb(3, 3, 3))
@@ -349,9 +1644,14 @@ fn ansi_items_test() {
         })
     );
     assert_eq!(
-        style(sc)(b"\x1b[38;5;3m").unwrap().1.unwrap(),
-        Style::from(AnsiStates {
-            style: sc,
+        style(b"\x1b[38;5;3m", sc, &options, &mut context)
+            .unwrap()
+            .1
+            .unwrap(),
+        Rendition::from(AnsiStates {
+            style: sc.style,
+            underline_style: sc.underline_style,
+            underline_color: sc.underline_color,
             items: vec![AnsiItem {
                 code: AnsiCode::SetForegroundColor,
                 color: Some(Color::Indexed(3))
@@ -360,9 +1660,14 @@ fn ansi_items_test() {
         })
     );
     assert_eq!(
-        style(sc)(b"\x1b[38;5;3;48;5;3m").unwrap().1.unwrap(),
-        Style::from(AnsiStates {
-            style: sc,
+        style(b"\x1b[38;5;3;48;5;3m", sc, &options, &mut context)
+            .unwrap()
+            .1
+            .unwrap(),
+        Rendition::from(AnsiStates {
+            style: sc.style,
+            underline_style: sc.underline_style,
+            underline_color: sc.underline_color,
             items: vec![
                 AnsiItem {
                     code: AnsiCode::SetForegroundColor,
@@ -377,9 +1682,14 @@ fn ansi_items_test() {
         })
     );
     assert_eq!(
-        style(sc)(b"\x1b[38;5;3;48;5;3;1m").unwrap().1.unwrap(),
-        Style::from(AnsiStates {
-            style: sc,
+        style(b"\x1b[38;5;3;48;5;3;1m", sc, &options, &mut context)
+            .unwrap()
+            .1
+            .unwrap(),
+        Rendition::from(AnsiStates {
+            style: sc.style,
+            underline_style: sc.underline_style,
+            underline_color: sc.underline_color,
             items: vec![
                 AnsiItem {
                     code: AnsiCode::SetForegroundColor,
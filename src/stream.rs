@@ -0,0 +1,191 @@
+use crate::{
+    Error, ParseOptions, ParsedText,
+    parser::{self, Context, OpenLine, Rendition},
+};
+use ratatui_core::{
+    style::Style,
+    text::{Line, Text},
+};
+
+/// A parser for input that arrives in chunks, e.g. from a pipe.
+///
+/// Chunks can end anywhere, also in the middle of an escape sequence or a UTF-8 encoded
+/// character. The parser keeps what it can't parse yet, and carries the style and the rest of
+/// its state from one chunk to the next. Lines are returned once their line break arrived, the
+/// last line without one by [`AnsiParser::finish`].
+///
+/// In the virtual screen mode (see [`ParseOptions::virtual_screen`]) the cursor can go back to
+/// any line, so all lines are returned by [`AnsiParser::finish`].
+///
+/// # Example
+///
+/// ```rust
+/// use ansi_to_tui::AnsiParser;
+/// use ratatui_core::style::{Color, Style};
+/// use ratatui_core::text::{Line, Span};
+///
+/// let red = Style::new().fg(Color::Rgb(255, 0, 0));
+/// let mut parser = AnsiParser::new();
+/// assert_eq!(parser.push(b"\x1b[38;2;")?, []);
+/// assert_eq!(parser.push(b"255;0;0mr\xc3")?, []);
+/// assert_eq!(parser.push(b"\xa9d\nmore")?, [Line::from(Span::styled("réd", red))]);
+/// assert_eq!(parser.style(), red);
+/// assert_eq!(parser.finish()?, [Line::from(Span::styled("more", red))]);
+/// # Ok::<(), ansi_to_tui::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct AnsiParser {
+    options: ParseOptions,
+    context: Context,
+    rendition: Rendition,
+    /// The last line if an alternate screen switch came before its line break.
+    open: OpenLine<'static>,
+    /// The lines parsed before an error, returned by the next parse.
+    parsed: Vec<Line<'static>>,
+    /// The bytes after the last complete line.
+    pending: Vec<u8>,
+    /// The offset, line and column of `pending` in the whole input, for the positions of errors.
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiParser {
+    /// Create a parser with the default options.
+    pub fn new() -> Self {
+        Self::with_options(&ParseOptions::default())
+    }
+
+    /// Create a parser that follows `options`.
+    pub fn with_options(options: &ParseOptions) -> Self {
+        Self {
+            options: options.clone(),
+            // Only the lines are returned
+            context: Context::text_only(&[], options),
+            rendition: Rendition::default(),
+            open: None,
+            parsed: Vec::new(),
+            pending: Vec::new(),
+            offset: 0,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    /// Parse the next chunk of the input, returning the lines it completed.
+    ///
+    /// When a line fails to parse, it is skipped and the error points at its position in the
+    /// whole input. The other lines of the chunk are returned by the next call, and later chunks
+    /// are parsed as usual.
+    ///
+    /// The bytes of a line are kept until its line break arrives, so a line without one grows
+    /// in memory with the input, whatever the [`Limits`](crate::Limits).
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<Line<'static>>, Error> {
+        self.pending.extend_from_slice(chunk);
+        self.parse(false)
+    }
+
+    /// Parse the rest of the input, returning the remaining lines.
+    ///
+    /// An escape sequence or UTF-8 encoded character that is still incomplete is handled like
+    /// at the end of [`IntoText::into_text`](crate::IntoText::into_text).
+    pub fn finish(mut self) -> Result<Vec<Line<'static>>, Error> {
+        self.parse(true)
+    }
+
+    /// The style at the end of the input so far.
+    pub fn style(&self) -> Style {
+        self.rendition.style
+    }
+
+    /// Parse the complete pending lines, and the rest and the screens at the end of the input
+    /// if `last`.
+    fn parse(&mut self, last: bool) -> Result<Vec<Line<'static>>, Error> {
+        // The screens and the open line still end the text after a limit was reached
+        if self.context.truncated() && !last {
+            self.pending.clear();
+            return Ok(std::mem::take(&mut self.parsed));
+        }
+        let input = self.pending.as_slice();
+        let options = &self.options;
+        let context = &mut self.context;
+        let mut parsed = ParsedText::default();
+        let mut open = self.open.take();
+        let mut error = None;
+        let (offset, line, column) = (self.offset, self.line, self.column);
+        let mut fail = |e| {
+            let e = Error::from_parse(input, e).shifted(offset, line, column);
+            error.get_or_insert(e);
+        };
+        context.set_input(input);
+        // Each line is parsed on its own, so a line that fails doesn't take the others with it
+        let mut len = 0;
+        while !context.truncated() {
+            let line_len = match line_len(&input[len..], options) {
+                Some(line_len) => line_len,
+                None if last && len < input.len() => input.len() - len,
+                None => break,
+            };
+            let line = &input[len..len + line_len];
+            len += line_len;
+            match parser::parse(
+                line,
+                self.rendition,
+                &mut open,
+                options,
+                context,
+                &mut parsed,
+            ) {
+                Ok((_, rendition)) => self.rendition = rendition,
+                Err(e) => fail(e),
+            }
+        }
+        if last {
+            let end = &input[input.len()..];
+            if let Err(e) = parser::finish(end, &mut open, options, context, &mut parsed) {
+                fail(e);
+            }
+        }
+        if context.truncated() {
+            len = input.len();
+        }
+        let lines = parser::into_owned(Text::from(parsed.text.lines)).lines;
+        self.open = open.map(|(line, attributes)| (parser::into_owned_line(line), attributes));
+        self.context.take(lines.len());
+        let done = &input[..len];
+        self.offset += len;
+        self.line += done.iter().filter(|&&c| c == b'\n').count();
+        self.column = match done.iter().rposition(|&c| c == b'\n') {
+            Some(i) => len - i - 1,
+            None => self.column + len,
+        };
+        self.pending.drain(..len);
+        self.parsed.extend(lines);
+        match error {
+            Some(e) => Err(e),
+            None => Ok(std::mem::take(&mut self.parsed)),
+        }
+    }
+}
+
+/// The length of the first line of `s` with its line break, if that arrived.
+///
+/// A line break ends every escape sequence but a control string, whose line breaks are skipped,
+/// and can't be part of a UTF-8 encoded character, so the line can be parsed without the rest. A
+/// lone `\r` ends a line too, unless it moves the cursor back (see
+/// [`ParseOptions::overwrite_on_carriage_return`]).
+fn line_len(s: &[u8], options: &ParseOptions) -> Option<usize> {
+    let cr = !options.overwrite_on_carriage_return;
+    let i = parser::find_line_break(s, cr, options)?;
+    match (s[i], s.get(i + 1)) {
+        (b'\r', Some(b'\n')) => Some(i + 2),
+        (b'\r', None) => None,
+        _ => Some(i + 1),
+    }
+}
@@ -1,4 +1,8 @@
-use crate::IntoText as _;
+use crate::{
+    AlternateScreen, AnsiParser, ControlStringKind, Encoding, Error, Hyperlink, IntoText as _,
+    IntoTextExt, Limit, Limits, Param, ParseOptions, Reason, Title, TitleKind, Token, Tokens,
+    UnderlineStyle,
+};
 use pretty_assertions::assert_eq;
 use ratatui_core::{
     style::{Color, Style, Stylize},
@@ -496,6 +500,167 @@ fn parses_truecolor_foreground_and_background() {
     }
 }
 
+#[test]
+fn parses_colon_separated_indexed_colors() {
+    let bytes: Vec<u8> = b"\x1b[38:5:208mFG\x1b[48:5:17mBG".to_vec();
+    let output = Text::from(Line::from(vec![
+        "FG".fg(Color::Indexed(208)),
+        "BG".fg(Color::Indexed(208)).bg(Color::Indexed(17)),
+    ]));
+    test_both(bytes, output);
+}
+
+#[test]
+fn parses_colon_separated_truecolor_with_and_without_color_space() {
+    let test_cases: [&[u8]; 4] = [
+        b"\x1b[38:2::255:0:0;48:2::0:0:255mHELLO",
+        b"\x1b[38:2:0:255:0:0;48:2:0:0:0:255mHELLO",
+        b"\x1b[38:2:255:0:0;48:2:0:0:255mHELLO",
+        b"\x1b[38:2::255:0:0m\x1b[48;2;0;0;255mHELLO",
+    ];
+    for bytes in test_cases {
+        let output = Text::from("HELLO".fg(Color::Rgb(255, 0, 0)).bg(Color::Rgb(0, 0, 255)));
+        test_both(bytes, output);
+    }
+}
+
+#[test]
+fn colon_separated_colors_chain_with_other_sgr_items() {
+    let bytes: Vec<u8> = b"\x1b[1;38:2::1:2:3;4mX".to_vec();
+    let output = Text::from("X".bold().fg(Color::Rgb(1, 2, 3)).underlined());
+    test_both(bytes, output);
+}
+
+#[test]
+fn sgr_4_sub_parameters_set_underline_style() {
+    let bytes: Vec<u8> = b"\x1b[4:3mcurly\x1b[4:2mdouble\x1b[4mplain\x1b[4:0mnone".to_vec();
+    let output = Text::from(Line::from(vec![
+        "curly".underlined(),
+        "double".underlined(),
+        "plain".underlined(),
+        "none".not_underlined(),
+    ]));
+    test_both(&bytes, output);
+
+    let parsed = bytes.into_parsed_text().unwrap();
+    let underline_styles: Vec<_> = parsed.span_attributes[0]
+        .iter()
+        .map(|attributes| attributes.underline_style)
+        .collect();
+    assert_eq!(
+        underline_styles,
+        [
+            UnderlineStyle::Curly,
+            UnderlineStyle::Double,
+            UnderlineStyle::Single,
+            UnderlineStyle::None,
+        ]
+    );
+}
+
+#[test]
+fn underline_style_is_carried_across_lines_until_reset() {
+    let bytes: Vec<u8> = b"\x1b[4:4mA\nB\x1b[0mC\n\x1b[4:5mD\x1b[24mE".to_vec();
+    let parsed = bytes.into_parsed_text().unwrap();
+    let underline_styles: Vec<Vec<_>> = parsed
+        .span_attributes
+        .iter()
+        .map(|line| line.iter().map(|a| a.underline_style).collect())
+        .collect();
+    assert_eq!(
+        underline_styles,
+        [
+            vec![UnderlineStyle::Dotted],
+            vec![UnderlineStyle::Dotted, UnderlineStyle::None],
+            vec![UnderlineStyle::Dashed, UnderlineStyle::None],
+        ]
+    );
+    assert_eq!(parsed.text, bytes.into_text().unwrap());
+}
+
+#[test]
+fn unknown_underline_styles_are_ignored() {
+    let bytes: Vec<u8> = b"\x1b[4:9;31mred".to_vec();
+    let output = Text::from("red".red());
+    test_both(&bytes, output);
+    let parsed = bytes.into_parsed_text().unwrap();
+    assert_eq!(
+        parsed.attributes(0, 0).unwrap().underline_style,
+        UnderlineStyle::None
+    );
+}
+
+#[test]
+fn sgr_58_arguments_are_not_read_as_separate_codes() {
+    let test_cases: [(&[u8], Color); 4] = [
+        (b"\x1b[4;58;2;255;0;0mX", Color::Rgb(255, 0, 0)),
+        (b"\x1b[4;58:2::255:0:0mX", Color::Rgb(255, 0, 0)),
+        (b"\x1b[4;58;5;208mX", Color::Indexed(208)),
+        (b"\x1b[4;58:5:208mX", Color::Indexed(208)),
+    ];
+    for (bytes, color) in test_cases {
+        let style = Style::new().underlined();
+        #[cfg(feature = "underline-color")]
+        let style = style.underline_color(color);
+        test_both(bytes, Text::from(Span::styled("X", style)));
+
+        let parsed = bytes.into_parsed_text().unwrap();
+        assert_eq!(
+            parsed.attributes(0, 0).unwrap().underline_color,
+            Some(color)
+        );
+    }
+}
+
+#[test]
+fn sgr_59_resets_underline_color() {
+    let bytes: Vec<u8> = b"\x1b[4;58;5;1mA\x1b[59mB".to_vec();
+    let parsed = bytes.into_parsed_text().unwrap();
+    let underline_colors: Vec<_> = parsed.span_attributes[0]
+        .iter()
+        .map(|attributes| attributes.underline_color)
+        .collect();
+    assert_eq!(underline_colors, [Some(Color::Indexed(1)), None]);
+
+    #[cfg(feature = "underline-color")]
+    assert_eq!(
+        parsed.text.lines[0].spans[1].style.underline_color,
+        Some(Color::Reset)
+    );
+}
+
+#[test]
+fn out_of_range_parameters_are_ignored_and_other_items_still_apply() {
+    let test_cases: [(&[u8], Text); 4] = [
+        (b"\x1b[38;5;300mX", Text::raw("X")),
+        (b"\x1b[38;5;300;1mX", Text::from("X".bold())),
+        (b"\x1b[38;2;1;256;3;4mX", Text::from("X".underlined())),
+        (b"\x1b[1000;31mX", Text::from("X".red())),
+    ];
+    for (bytes, output) in test_cases {
+        test_both(bytes, output);
+    }
+}
+
+#[test]
+fn zero_padded_parameters_are_parsed() {
+    let bytes: Vec<u8> = b"\x1b[001;038;005;0009mX".to_vec();
+    let output = Text::from("X".bold().fg(Color::Indexed(9)));
+    test_both(bytes, output);
+}
+
+#[test]
+fn empty_parameters_are_treated_as_0() {
+    let test_cases: [(&[u8], Style); 3] = [
+        (b"\x1b[31m\x1b[;1mX", Style::reset().bold()),
+        (b"\x1b[1;;4mX", Style::reset().underlined()),
+        (b"\x1b[31;mX", Style::reset()),
+    ];
+    for (bytes, style) in test_cases {
+        test_both(bytes, Text::from(Span::styled("X", style)));
+    }
+}
+
 #[test]
 fn carries_style_across_lines_and_handles_resets() {
     let bytes: Vec<u8> = String::from(
@@ -523,14 +688,935 @@ fn carries_style_across_lines_and_handles_resets() {
     test_both(bytes, output);
 }
 
+#[test]
+fn ignores_two_and_three_byte_escape_sequences() {
+    let bytes: Vec<u8> = b"\x1b7A\x1b(BB\x1b=C".to_vec();
+    let output = Text::from(Line::from(vec![
+        Span::raw("A"),
+        Span::raw("B"),
+        Span::raw("C"),
+    ]));
+    test_both(bytes, output);
+}
+
+#[test]
+fn tokenizes_text_controls_and_escape_sequences() {
+    let bytes = b"\x1b[1;38:5:208mA\tB\x1b[2K\x1b]0;title\x07\x1b(0\r\n\rC\x1b";
+    let tokens: Vec<_> = Tokens::new(bytes).collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::Sgr(vec![Param::new(1), Param::with_subparams(38, [5, 208])]),
+            Token::Text("A"),
+            Token::Control(b'\t'),
+            Token::Text("B"),
+            Token::Csi {
+                private_marker: None,
+                params: vec![Param::new(2)],
+                intermediates: b"",
+                final_byte: b'K',
+            },
+            Token::Osc(b"0;title"),
+            Token::Escape(b"\x1b(0"),
+            Token::Newline,
+            Token::Control(b'\r'),
+            Token::Text("C"),
+            Token::Escape(b"\x1b"),
+        ]
+    );
+}
+
+#[test]
+fn tokenizes_csi_private_markers_and_intermediates() {
+    let tokens: Vec<_> = Tokens::new(b"\x1b[?1049h\x1b[4 q\x1b[>m").collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::Csi {
+                private_marker: Some(b'?'),
+                params: vec![Param::new(1049)],
+                intermediates: b"",
+                final_byte: b'h',
+            },
+            Token::Csi {
+                private_marker: None,
+                params: vec![Param::new(4)],
+                intermediates: b" ",
+                final_byte: b'q',
+            },
+            Token::Csi {
+                private_marker: Some(b'>'),
+                params: vec![],
+                intermediates: b"",
+                final_byte: b'm',
+            },
+        ]
+    );
+}
+
+#[test]
+fn tokenizes_malformed_sequences_and_invalid_utf8() {
+    let tokens: Vec<_> = Tokens::new(b"\x1b[0\x1b[mA\xffB\x1b[").collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::Escape(b"\x1b[0"),
+            Token::Sgr(vec![]),
+            Token::Text("A"),
+            Token::Invalid(b"\xff"),
+            Token::Text("B"),
+            Token::Escape(b"\x1b["),
+        ]
+    );
+}
+
+#[test]
+fn c1_controls_are_read_as_their_7bit_equivalents() {
+    let options = ParseOptions::new().c1_controls(true);
+    let bytes: Vec<u8> = b"\x9b31mred\x9b0m\x9d0;title\x9cplain\x9b?25h".to_vec();
+    let output = Text::from(Line::from(vec![
+        "red".red(),
+        Span::styled("plain", Style::reset()),
+    ]));
+    test_both_with(bytes, &options, output);
+}
+
+#[test]
+fn c1_controls_do_not_match_utf8_continuation_bytes() {
+    // U+01DB is encoded as `C7 9B`, and U+015C as `C5 9C`
+    let options = ParseOptions::new().c1_controls(true);
+    let bytes = "\x1b[1mǛ\x1b]0;Ŝ\x07Ŝ".as_bytes();
+    let output = Text::from(Line::from(vec!["Ǜ".bold(), "Ŝ".bold()]));
+    test_both_with(bytes, &options, output);
+
+    let tokens: Vec<_> = Tokens::with_options(b"\x9b1m\xc7\x9b\x9dx\x9c", &options).collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::Sgr(vec![Param::new(1)]),
+            Token::Text("Ǜ"),
+            Token::Osc(b"x"),
+        ]
+    );
+}
+
+#[test]
+fn osc_is_terminated_by_bel_or_string_terminator() {
+    let bytes: Vec<u8> = b"\x1b]0;title\x1b\\A\x1b]2;other\x07B\x07".to_vec();
+    let output = Text::from(Line::from(vec![Span::raw("A"), Span::raw("B\x07")]));
+    test_both(bytes, output);
+}
+
+#[test]
+fn unterminated_osc_stops_at_newline_or_next_escape_sequence() {
+    let bytes: Vec<u8> = b"\x1b]0;title\nnext\x1b]0;title\x1b[31mred".to_vec();
+    let output = Text::from(vec![
+        Line::from(""),
+        Line::from(vec![Span::raw("next"), "red".red()]),
+    ]);
+    test_both(bytes, output);
+
+    let tokens: Vec<_> = Tokens::new(b"\x1b]0;a\nb\x1b]8;;x\x1b\\").collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::Escape(b"\x1b]0;a"),
+            Token::Newline,
+            Token::Text("b"),
+            Token::Osc(b"8;;x"),
+        ]
+    );
+}
+
+#[test]
+fn unterminated_osc_is_limited_in_length() {
+    let mut bytes = b"\x1b]0;".to_vec();
+    bytes.resize(Limits::DEFAULT_MAX_ESCAPE_LEN + 2, b'a');
+    bytes.extend_from_slice(b"bcd");
+    let output = Text::from(Line::from(vec![Span::raw("bcd")]));
+    test_both(bytes, output);
+}
+
+#[test]
+fn skips_dcs_apc_pm_and_sos_strings() {
+    let bytes: Vec<u8> =
+        b"A\x1bPq#0;2;0;0;0#0~~@@vv\x1b\\B\x1b_Gf=100;AAAA\x1b\\C\x1b^pm\x1b\\D\x1bXsos\x1b\\E"
+            .to_vec();
+    let output = Text::from(Line::from(vec![
+        Span::raw("A"),
+        Span::raw("B"),
+        Span::raw("C"),
+        Span::raw("D"),
+        Span::raw("E"),
+    ]));
+    test_both(bytes, output);
+}
+
+#[test]
+fn tmux_passthrough_doubled_escapes_do_not_end_the_string() {
+    let bytes: &[u8] = b"\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\x1b\\\x1b\\text";
+    let tokens: Vec<_> = Tokens::new(bytes).collect();
+    assert_eq!(
+        tokens,
+        [
+            Token::ControlString {
+                kind: ControlStringKind::Dcs,
+                payload: b"tmux;\x1b\x1b]52;c;aGk=\x07\x1b\x1b\\",
+            },
+            Token::Text("text"),
+        ]
+    );
+}
+
+#[test]
+fn unterminated_control_string_stops_at_the_next_escape() {
+    let bytes: Vec<u8> = b"\x1bPpayload\nnext\x1b[1mbold".to_vec();
+    let output = Text::from(Line::from(Span::styled("bold", Style::default().bold())));
+    test_both(bytes, output);
+}
+
+#[test]
+fn control_strings_go_on_over_line_breaks() {
+    let sixel: &[u8] = b"\x1bPq#0;2;0;0;0\n#0~~-\n#0@@\x1b\\text\nnext";
+    let output = Text::from(vec![Line::from("text"), Line::from("next")]);
+    test_both(sixel, output.clone());
+
+    let kitty: &[u8] = b"\x1b_Gf=100;AAAA\nBBBB\r\nCCCC\x1b\\text\nnext";
+    test_both(kitty, output.clone());
+    let options = ParseOptions::new().overwrite_on_carriage_return(true);
+    test_both_with(kitty, &options, output.clone());
+    let options = ParseOptions::new().virtual_screen(80, None);
+    test_both_with(kitty, &options, output.clone());
+
+    // A DCS introducer inside another sequence doesn't start a control string
+    let options = ParseOptions::new().c1_controls(true);
+    let output = Text::from(vec![
+        Line::from(vec![Span::raw("a"), Span::raw("b")]),
+        Line::from("c"),
+    ]);
+    test_both_with(b"a\x9b\x90:lb\nc", &options, output);
+
+    // A line isn't complete while a control string is open
+    let mut parser = AnsiParser::new();
+    assert_eq!(parser.push(&sixel[..20]).unwrap(), []);
+    assert_eq!(parser.push(&sixel[20..]).unwrap(), [Line::from("text")]);
+    assert_eq!(parser.finish().unwrap(), [Line::from("next")]);
+}
+
+#[test]
+fn control_string_handler_receives_payloads() {
+    use std::sync::{Arc, Mutex};
+
+    let payloads = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&payloads);
+    let options = ParseOptions::new()
+        .c1_controls(true)
+        .on_control_string(move |kind, payload| {
+            sink.lock().unwrap().push((kind, payload.to_vec()));
+        });
+    let bytes: &[u8] = b"\x1b_Ga=T\x1b\\A\x90q\x9cB";
+    let output = Text::from(Line::from(vec![Span::raw("A"), Span::raw("B")]));
+    let _ = bytes.into_text_with(&options).unwrap();
+    assert_eq!(
+        *payloads.lock().unwrap(),
+        [
+            (ControlStringKind::Apc, b"Ga=T".to_vec()),
+            (ControlStringKind::Dcs, b"q".to_vec()),
+        ]
+    );
+    test_both_with(bytes, &options, output);
+}
+
+#[test]
+fn osc_8_hyperlinks_are_attached_to_spans() {
+    let bytes: Vec<u8> =
+        b"see \x1b]8;id=docs;https://ratatui.rs\x1b\\the \x1b[1mdocs\x1b[0m\x1b]8;;\x1b\\ now"
+            .to_vec();
+    let output = Text::from(Line::from(vec![
+        Span::raw("see "),
+        Span::raw("the "),
+        "docs".bold(),
+        Span::styled(" now", Style::reset()),
+    ]));
+    test_both(&bytes, output);
+
+    let parsed = bytes.into_parsed_text().unwrap();
+    let link = Hyperlink {
+        uri: "https://ratatui.rs".to_string(),
+        id: Some("docs".to_string()),
+    };
+    assert_eq!(parsed.hyperlinks, std::slice::from_ref(&link));
+    let links: Vec<_> = (0..4).map(|span| parsed.hyperlink(0, span)).collect();
+    assert_eq!(links, [None, Some(&link), Some(&link), None]);
+}
+
+#[test]
+fn osc_8_hyperlinks_continue_across_lines_and_are_deduplicated() {
+    let bytes: Vec<u8> =
+        b"\x1b]8;;file:///a\x07a\nb\x1b]8;;\x07\n\x1b]8;;file:///b\x07c\x1b]8;;file:///a\x07d"
+            .to_vec();
+    let parsed = bytes.into_parsed_text().unwrap();
+    assert_eq!(
+        parsed.hyperlinks,
+        [
+            Hyperlink {
+                uri: "file:///a".to_string(),
+                id: None,
+            },
+            Hyperlink {
+                uri: "file:///b".to_string(),
+                id: None,
+            },
+        ]
+    );
+    let links: Vec<Vec<_>> = parsed
+        .span_attributes
+        .iter()
+        .map(|line| line.iter().map(|a| a.hyperlink).collect())
+        .collect();
+    assert_eq!(
+        links,
+        [vec![Some(0)], vec![Some(0)], vec![Some(1), Some(0)]]
+    );
+    assert_eq!(parsed.text, bytes.into_text().unwrap());
+}
+
+#[test]
+fn osc_0_1_and_2_title_changes_are_recorded_with_their_line() {
+    let bytes: Vec<u8> =
+        b"\x1b]0;~/src\x07$ make\n\x1b]2;make\x1b\\building\n\x1b]1;icon\x07done".to_vec();
+    let output = Text::from(vec![
+        Line::from("$ make"),
+        Line::from("building"),
+        Line::from("done"),
+    ]);
+    test_both(&bytes, output);
+
+    let parsed = bytes.into_parsed_text().unwrap();
+    assert_eq!(
+        parsed.titles,
+        [
+            Title {
+                kind: TitleKind::IconAndWindow,
+                title: "~/src".to_string(),
+                line: 0,
+            },
+            Title {
+                kind: TitleKind::Window,
+                title: "make".to_string(),
+                line: 1,
+            },
+            Title {
+                kind: TitleKind::Icon,
+                title: "icon".to_string(),
+                line: 2,
+            },
+        ]
+    );
+    assert_eq!(parsed.title(), Some("make"));
+}
+
+#[test]
+fn osc_4_10_and_11_redefine_the_palette_when_tracked() {
+    let bytes: Vec<u8> = b"\x1b[31mred \x1b]4;1;rgb:ff/80/00\x07\x1b[31morange \x1b[38;5;1mindexed\n\
+        \x1b]10;#ffffff\x07\x1b]11;rgb:0/0/0\x1b\\\x1b[0mdefault \x1b]104;1\x07\x1b]110\x07\x1b[31mred"
+        .to_vec();
+    let options = ParseOptions::new().track_palette(true);
+    let orange = Color::Rgb(255, 128, 0);
+    let output = Text::from(vec![
+        Line::from(vec![
+            Span::styled("red ", Style::default().red()),
+            Span::styled("orange ", Style::default().fg(orange)),
+            Span::styled("indexed", Style::default().fg(orange)),
+        ]),
+        Line::from(vec![
+            Span::styled(
+                "default ",
+                Style::reset()
+                    .fg(Color::Rgb(255, 255, 255))
+                    .bg(Color::Rgb(0, 0, 0)),
+            ),
+            Span::styled("red", Style::reset().red().bg(Color::Rgb(0, 0, 0))),
+        ]),
+    ]);
+    test_both_with(&bytes, &options, output);
+
+    // Without tracking, the colors are kept as written
+    let output = Text::from(vec![
+        Line::from(vec![
+            Span::styled("red ", Style::default().red()),
+            Span::styled("orange ", Style::default().red()),
+            Span::styled("indexed", Style::default().fg(Color::Indexed(1))),
+        ]),
+        Line::from(vec![
+            Span::styled("default ", Style::reset()),
+            Span::styled("red", Style::reset().red()),
+        ]),
+    ]);
+    test_both(&bytes, output);
+}
+
+#[test]
+fn osc_133_marks_split_the_text_into_command_blocks() {
+    let bytes: Vec<u8> = b"\x1b]133;A\x07$ \x1b]133;B\x07ls\n\x1b]133;C\x07a\nb\n\x1b]133;D;0\x07\
+        \x1b]133;A\x07$ \x1b]133;B\x07false\n\x1b]133;C\x1b\\\x1b]133;D;1\x07\
+        \x1b]133;A\x07$ \x1b]133;B\x07"
+        .to_vec();
+    let output = Text::from(vec![
+        Line::from(vec![Span::raw("$ "), Span::raw("ls")]),
+        Line::from("a"),
+        Line::from("b"),
+        Line::from(vec![Span::raw("$ "), Span::raw("false")]),
+        Line::from("$ "),
+    ]);
+    test_both(&bytes, output);
+
+    let parsed = bytes.into_parsed_text().unwrap();
+    let blocks: Vec<_> = parsed
+        .command_blocks
+        .iter()
+        .map(|block| {
+            (
+                block.lines.clone(),
+                block.command,
+                block.output,
+                block.exit_code,
+            )
+        })
+        .collect();
+    assert_eq!(
+        blocks,
+        [
+            (0..3, Some(0), Some(1), Some(0)),
+            (3..4, Some(3), Some(4), Some(1)),
+            (4..5, Some(4), None, None),
+        ]
+    );
+}
+
+#[test]
+fn carriage_return_overwrites_the_line_when_enabled() {
+    let bytes = b"\x1b[32m[##  ]\x1b[0m  50%\r\x1b[32m[####]\x1b[0m 100%\r\n\
+        \x1b[1mbold\x1b[0m text\rB\nwide\xe7\x95\x8c\r\x1b[31mabcde\n";
+    let options = ParseOptions::new().overwrite_on_carriage_return(true);
+    let output = Text::from(vec![
+        Line::from(vec![
+            Span::styled("[####]", Style::reset().green()),
+            Span::styled(" 100%", Style::reset()),
+        ]),
+        Line::from(vec![
+            Span::styled("B", Style::reset()),
+            Span::styled("old", Style::reset().bold()),
+            Span::styled(" text", Style::reset()),
+        ]),
+        Line::from(vec![
+            Span::styled("abcde", Style::reset().red()),
+            Span::styled(" ", Style::reset()),
+        ]),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    // Without the option, every `\r` starts a new line
+    let text = bytes.into_text().unwrap();
+    assert_eq!(text.lines.len(), 6);
+
+    // EL clears the rest of the line, like progress output that rewrites a shorter line
+    let bytes = b"downloading 100%\r\x1b[Kdone\n";
+    test_both_with(bytes, &options, Text::from("done"));
+}
+
+#[test]
+fn backspace_overstrike_is_decoded_when_enabled() {
+    let bytes = b"S\x08SY\x08YN\x08NO\x08OP\x08PS\x08SI\x08IS\x08S\n\
+        \x20      l\x08ls\x08s [_\x08f_\x08i_\x08l_\x08e]\n\
+        \xe7\x95\x8c\x08\xe7\x95\x8c ab\x08\x08x\n";
+    let options = ParseOptions::new().overstrike(true);
+    let output = Text::from(vec![
+        Line::from(Span::styled("SYNOPSIS", Style::default().bold())),
+        Line::from(vec![
+            Span::raw("       "),
+            Span::styled("ls", Style::default().bold()),
+            Span::raw(" ["),
+            Span::styled("file", Style::default().underlined()),
+            Span::raw("]"),
+        ]),
+        Line::from(vec![
+            Span::styled("\u{754c}", Style::default().bold()),
+            Span::raw(" xb"),
+        ]),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    // Without the option, the backspaces are kept
+    let text = bytes.into_text().unwrap();
+    assert_eq!(
+        text.lines[0],
+        Line::from("S\x08SY\x08YN\x08NO\x08OP\x08PS\x08SI\x08IS\x08S")
+    );
+}
+
+#[test]
+fn tabs_are_expanded_to_tab_stops_when_enabled() {
+    let bytes = "a\tb\n\u{754c}\u{754c}\u{754c}\t\x1b[31mx\x1b[0m\n\
+        \x1b[3g  \x1bHx\n\tc\n\x1b[0gab\td\n";
+    let options = ParseOptions::new().expand_tabs(Some(4));
+    let output = Text::from(vec![
+        Line::from("a   b"),
+        Line::from(vec![
+            Span::raw("\u{754c}\u{754c}\u{754c}  "),
+            Span::styled("x", Style::default().red()),
+        ]),
+        Line::from(Span::styled("  x", Style::reset())),
+        // Only the tab stop set with HTS is left
+        Line::from(vec![Span::raw("  "), Span::styled("c", Style::reset())]),
+        // Without a tab stop after the cursor, tabs move one column
+        Line::from(vec![
+            Span::styled("ab", Style::reset()),
+            Span::raw(" "),
+            Span::styled("d", Style::reset()),
+        ]),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    // Without the option, the tabs are kept
+    let text = bytes.into_text().unwrap();
+    assert_eq!(text.lines[0], Line::from("a\tb"));
+}
+
+#[test]
+fn virtual_screen_applies_cursor_movement_and_erases() {
+    // A progress display that redraws its lines in place
+    let bytes = b"\x1b[1mBuilding\x1b[0m\n\
+        [1/2] a\n[2/2] b\n\
+        \x1b[2A\x1b[2K\x1b[32mok\x1b[0m a\x1b[B\r\x1b[5C\x1b[K!\n";
+    let options = ParseOptions::new().virtual_screen(20, None);
+    let output = Text::from(vec![
+        Line::from(Span::styled("Building", Style::default().bold())),
+        Line::from(vec![
+            Span::styled("ok", Style::reset().green()),
+            Span::styled(" a", Style::reset()),
+        ]),
+        Line::from(Span::styled("[2/2]!", Style::reset())),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    // Text wraps at the last column and the screen scrolls at the last row
+    let bytes = b"one\ntwo\nthree\nfour\x1b[1;3Hx\x1b[2;1H\x1b[1J";
+    let options = ParseOptions::new().virtual_screen(4, Some(3));
+    let output = Text::from(vec![
+        Line::from(""),
+        // The wrapped "e" of "three", erased
+        Line::from(" "),
+        Line::from("four"),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    let bytes = b"abcdef\x1b[Hx\x1b[3GZ\x1b[J";
+    let output = Text::from(vec![Line::from("xbZ")]);
+    test_both_with(bytes, &options, output);
+}
+
+#[test]
+fn alternate_screen_output_is_handled_as_chosen() {
+    let bytes = b"$ top\n\x1b[?1049h\x1b[H\x1b[2Jload 1.0\n\x1b[1mPID\x1b[0m\
+        \x1b[H\x1b[2Jload 2.0\n\x1b[1mPID\x1b[0m\x1b[?1049l$ exit\n";
+    let frame = vec![
+        Line::from(Span::styled("load 2.0", Style::reset())),
+        Line::from(Span::styled("PID", Style::reset().bold())),
+    ];
+    let prompt = Line::from(Span::styled("$ exit", Style::reset()));
+
+    let options = ParseOptions::new().alternate_screen(AlternateScreen::Drop);
+    let output = Text::from(vec![Line::from("$ top"), prompt.clone()]);
+    test_both_with(bytes, &options, output);
+
+    let options = ParseOptions::new().alternate_screen(AlternateScreen::FinalFrame);
+    let mut lines = vec![Line::from("$ top")];
+    lines.extend(frame.clone());
+    lines.push(prompt.clone());
+    test_both_with(bytes, &options, Text::from(lines));
+
+    let options = ParseOptions::new().alternate_screen(AlternateScreen::Separate);
+    let output = Text::from(vec![Line::from("$ top"), prompt.clone()]);
+    test_both_with(bytes, &options, output);
+    let parsed = bytes.into_parsed_text_with(&options).unwrap();
+    assert_eq!(parsed.alternate_screens, [Text::from(frame)]);
+
+    // Inline keeps every frame
+    let text = bytes.into_text().unwrap();
+    assert_eq!(text.lines.len(), 4);
+
+    // The line before the switch continues after it
+    let options = ParseOptions::new().alternate_screen(AlternateScreen::Drop);
+    let bytes = b"abc\x1b[?1049hX\x1b[?1049ldef";
+    let output = Text::from(Line::from(vec![Span::raw("abc"), Span::raw("def")]));
+    test_both_with(bytes, &options, output.clone());
+    let mut parser = AnsiParser::with_options(&options);
+    assert_eq!(parser.push(b"abc\x1b[?1049hX\n").unwrap(), []);
+    assert_eq!(parser.push(b"\x1b[?1049ldef\n").unwrap(), output.lines);
+}
+
+#[test]
+fn dec_special_graphics_are_translated_to_box_drawing() {
+    let bytes = b"\x1b(0lqqk\x1b(B\n\x1b(0x\x1b(B ok \x1b(0x\x1b(B\n\x1b)0\x0emqqj\x0f mqqj";
+    let output = Text::from(vec![
+        Line::from("┌──┐"),
+        Line::from(vec![Span::raw("│"), Span::raw(" ok "), Span::raw("│")]),
+        Line::from(vec![Span::raw("└──┘"), Span::raw(" mqqj")]),
+    ]);
+    test_both(bytes, output);
+}
+
+#[test]
+fn invalid_utf8_fails_at_its_position_or_is_replaced() {
+    let bytes = b"ok\n\x1b[31mbad \xc3\x28 byte\x1b[0m\nmore";
+    let Err(Error::Utf8Error(e)) = bytes.into_text() else {
+        panic!("expected a UTF-8 error");
+    };
+    assert_eq!((e.valid_up_to(), e.error_len()), (12, Some(1)));
+    let position = e.position();
+    assert_eq!((position.line, position.column), (2, 10));
+    assert_eq!(position.snippet, r"\xc3( byte\x1b[0m\nmore");
+    assert_eq!(
+        e.to_string(),
+        r"invalid utf-8 sequence of 1 bytes at line 2, column 10 (`\xc3( byte\x1b[0m\nmore`)"
+    );
+    #[cfg(feature = "zero-copy")]
+    assert_eq!(bytes.to_text(), Err(Error::Utf8Error(e)));
+
+    let options = ParseOptions::new().lossy_utf8(true);
+    let output = Text::from(vec![
+        Line::from("ok"),
+        Line::from(Span::styled("bad \u{fffd}( byte", Style::default().red())),
+        Line::from(Span::styled("more", Style::reset())),
+    ]);
+    test_both_with(bytes, &options, output);
+}
+
+#[test]
+fn strict_mode_rejects_unknown_and_malformed_sequences() {
+    let options = ParseOptions::new().strict(true);
+    let rejected = |bytes: &[u8]| match bytes.into_text_with(&options) {
+        Err(Error::Rejected { position, reason }) => (position.offset, reason),
+        other => panic!("expected a rejection, got {other:?}"),
+    };
+    assert_eq!(rejected(b"a\x1b[1;26mb"), (1, Reason::UnknownSgr(26)));
+    for code in [10, 11, 20, 21] {
+        let bytes = format!("\x1b[{code}mb");
+        assert_eq!(rejected(bytes.as_bytes()), (0, Reason::UnknownSgr(code)));
+    }
+    assert_eq!(
+        rejected(b"\x1b[4:9mb"),
+        (0, Reason::UnknownUnderlineStyle(9))
+    );
+    assert_eq!(
+        rejected(b"ab\x1b[38;3;1mc"),
+        (2, Reason::InvalidColorType(3))
+    );
+    assert_eq!(rejected(b"\x1b[48:2:255:0m"), (0, Reason::TruncatedColor));
+    assert_eq!(rejected(b"\x1b[38;2;255;0m"), (0, Reason::TruncatedColor));
+    assert_eq!(rejected(b"\x1b[38;5;256m"), (0, Reason::ColorOutOfRange));
+    assert_eq!(rejected(b"ok\n\x1b[31"), (3, Reason::UnterminatedCsi));
+    assert_eq!(rejected(b"\x1b[3\x01m"), (0, Reason::MalformedCsi));
+    assert_eq!(rejected(b"\x1b]0;title\nx"), (0, Reason::UnterminatedOsc));
+    assert_eq!(
+        rejected(b"\x1bPq#0"),
+        (0, Reason::UnterminatedControlString)
+    );
+    assert_eq!(rejected(b"ab\x1b"), (2, Reason::UnterminatedEscape));
+
+    let Err(e) = b"ok\n  \x1b[26m".into_text_with(&options) else {
+        panic!("expected a rejection");
+    };
+    assert_eq!(
+        e.to_string(),
+        r"unknown SGR code 26 at line 2, column 3 (`\x1b[26m`)"
+    );
+
+    // Everything that is understood, or deliberately ignored, still parses.
+    let bytes = b"\x1b[1;38;2;255;0;0m\x1b]0;title\x07\x1b[2J\x1b7red\x1b[0m";
+    let output = Text::from(Line::from(Span::styled(
+        "red",
+        Style::default().bold().fg(Color::Rgb(255, 0, 0)),
+    )));
+    test_both_with(bytes, &options, output);
+    assert!(b"\x1b[26m\x1b[31".into_text().is_ok());
+}
+
+#[test]
+fn diagnostics_list_what_the_text_leaves_out() {
+    let bytes = b"\x1b[1;26mbold\x1b[?25l\x1b]52;c;eA==\x07\x1b7\x1b]0;t\x07\x1b[31";
+    let parsed = bytes.into_parsed_text().unwrap();
+    let diagnostics: Vec<_> = parsed
+        .diagnostics
+        .iter()
+        .map(|d| (d.range.clone(), d.reason))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (0..7, Reason::UnknownSgr(26)),
+            (11..17, Reason::UnsupportedCsi(b'l')),
+            (17..29, Reason::UnsupportedOsc(Some(52))),
+            (29..31, Reason::UnsupportedEscape),
+            (37..41, Reason::UnterminatedCsi),
+        ]
+    );
+    assert_eq!(
+        parsed.text,
+        Text::from(Span::styled("bold", Style::default().bold()))
+    );
+
+    // What the options apply isn't reported, lossy UTF-8 is.
+    let options = ParseOptions::new()
+        .virtual_screen(10, None)
+        .track_palette(true)
+        .lossy_utf8(true);
+    let parsed = b"\x1b[2J\x1b]4;1;#ff0000\x07a\xffb\x1b[H"
+        .into_parsed_text_with(&options)
+        .unwrap();
+    assert_eq!(parsed.diagnostics.len(), 1);
+    assert_eq!(parsed.diagnostics[0].range, 19..20);
+    assert_eq!(parsed.diagnostics[0].reason, Reason::InvalidUtf8);
+    assert!(
+        b"plain \x1b[1mtext"
+            .into_parsed_text()
+            .unwrap()
+            .diagnostics
+            .is_empty()
+    );
+}
+
+#[test]
+fn legacy_encodings_are_decoded_when_chosen() {
+    let bytes = b"\x1b[1;33m\xc9\xcd\xbb\x1b[0m caf\xe9\n\xb0\xb1\xb2\xdb";
+    assert!(bytes.into_text().is_err());
+
+    let options = ParseOptions::new().encoding(Encoding::Cp437);
+    let output = Text::from(vec![
+        Line::from(vec![
+            Span::styled("╔═╗", Style::default().bold().yellow()),
+            Span::styled(" cafΘ", Style::reset()),
+        ]),
+        Line::from(Span::styled("░▒▓█", Style::reset())),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    let options = ParseOptions::new().encoding(Encoding::Latin1);
+    let output = Text::from(vec![
+        Line::from(vec![
+            Span::styled("É\u{cd}»", Style::default().bold().yellow()),
+            Span::styled(" café", Style::reset()),
+        ]),
+        Line::from(Span::styled("°±²Û", Style::reset())),
+    ]);
+    test_both_with(bytes, &options, output);
+
+    // Latin-1 C1 controls aren't mistaken for UTF-8
+    let options = ParseOptions::new()
+        .encoding(Encoding::Latin1)
+        .c1_controls(true);
+    let output = Text::from(Line::from(vec![
+        Span::raw("\u{e9}"),
+        Span::styled("red", Style::default().red()),
+    ]));
+    test_both_with(b"\xe9\x9b31mred", &options, output);
+}
+
+#[test]
+fn limits_truncate_the_text_or_fail() {
+    let exceeded = |bytes: &[u8], limits: Limits| {
+        let options = ParseOptions::new().limits(limits.truncate(false));
+        match bytes.into_text_with(&options) {
+            Err(Error::LimitExceeded { position, limit }) => (position.offset, limit),
+            other => panic!("expected an exceeded limit, got {other:?}"),
+        }
+    };
+
+    let bytes = b"a\nb\nc";
+    let limits = Limits::new().max_lines(2);
+    let output = Text::from(vec![Line::from("a"), Line::from("b")]);
+    test_both_with(bytes, &ParseOptions::new().limits(limits), output.clone());
+    assert_eq!(exceeded(bytes, limits), (4, Limit::Lines));
+    let options = ParseOptions::new().limits(limits).virtual_screen(10, None);
+    test_both_with(bytes, &options, output);
+
+    // Moving the cursor down doesn't allocate the rows it passes
+    let moves = b"\x1b[65535B\x1b[K".repeat(2000);
+    let bytes = [b"a".as_slice(), &moves, b"b"].concat();
+    let limits = Limits::new().max_lines(10);
+    let options = ParseOptions::new().limits(limits).virtual_screen(10, None);
+    test_both_with(&bytes, &options, Text::from("a"));
+    let options = options.limits(limits.truncate(false));
+    assert!(matches!(
+        bytes.into_text_with(&options),
+        Err(Error::LimitExceeded {
+            limit: Limit::Lines,
+            ..
+        })
+    ));
+
+    let bytes = b"\x1b[31ma\x1b[32mb\x1b[33mc\nd";
+    let limits = Limits::new().max_spans_per_line(2);
+    let output = Text::from(vec![
+        Line::from(vec![
+            Span::styled("a", Style::default().red()),
+            Span::styled("b", Style::default().green()),
+        ]),
+        Line::from(Span::styled("d", Style::default().yellow())),
+    ]);
+    test_both_with(bytes, &ParseOptions::new().limits(limits), output);
+    assert_eq!(exceeded(bytes, limits), (12, Limit::SpansPerLine));
+    // The escape sequences after the limit still apply to the next line
+    let bytes = b"a\x1b[31mb\x1b[32mc\nd";
+    let limits = Limits::new().max_spans_per_line(1);
+    let output = Text::from(vec![
+        Line::from("a"),
+        Line::from(Span::styled("d", Style::default().green())),
+    ]);
+    test_both_with(bytes, &ParseOptions::new().limits(limits), output.clone());
+    let options = ParseOptions::new()
+        .limits(limits)
+        .overwrite_on_carriage_return(true);
+    test_both_with(bytes, &options, output);
+
+    let bytes = b"abc\x1b[1mde\x1b[0mfg\nhi";
+    let limits = Limits::new().max_output_len(5);
+    let output = Text::from(Line::from(vec![
+        Span::raw("abc"),
+        Span::styled("de", Style::default().bold()),
+    ]));
+    test_both_with(bytes, &ParseOptions::new().limits(limits), output);
+    assert_eq!(exceeded(bytes, limits), (0, Limit::OutputLen));
+
+    // The text written over with `\r` and the cursor moves count while they are written
+    let bytes = [b"x".repeat(1000).as_slice(), b"\ryz\n"].concat();
+    let options = ParseOptions::new()
+        .limits(limits.max_output_len(100))
+        .overwrite_on_carriage_return(true);
+    test_both_with(&bytes, &options, Text::from("x".repeat(100)));
+    let options = ParseOptions::new()
+        .limits(Limits::new().max_output_len(100))
+        .virtual_screen(1000, None);
+    test_both_with(b"ab\x1b[200Gc", &options, Text::from("ab"));
+    let options = options.limits(Limits::new().max_output_len(100).truncate(false));
+    assert!(matches!(
+        b"a\x1b[200Gb\x1b[Ha".into_text_with(&options),
+        Err(Error::LimitExceeded {
+            limit: Limit::OutputLen,
+            ..
+        })
+    ));
+
+    let bytes = b"\x1b[1;1;1;1;1;31mred";
+    let limits = Limits::new().max_escape_len(8);
+    let output = Text::from(Line::from("red"));
+    test_both_with(bytes, &ParseOptions::new().limits(limits), output);
+    assert_eq!(exceeded(bytes, limits), (0, Limit::EscapeLen));
+    assert_eq!(
+        exceeded(b"\x1b]0;a long title\x07", limits),
+        (0, Limit::EscapeLen)
+    );
+
+    let options = ParseOptions::new().limits(Limits::new().max_lines(1).truncate(false));
+    let Err(e) = b"a\nb".into_text_with(&options) else {
+        panic!("expected an exceeded limit");
+    };
+    assert_eq!(
+        e.to_string(),
+        "line limit exceeded at line 2, column 1 (`b`)"
+    );
+}
+
+#[test]
+fn ansi_parser_matches_the_whole_input_at_any_chunk_boundary() {
+    let bytes =
+        "\x1b[1;38;2;255;128;0mcafé\x1b]8;;https://x\x1b\\ link\r\n\x1b[4:3mnext\x1b[0m\n\nend"
+            .as_bytes();
+    let text = bytes.into_text().unwrap();
+    for split in 0..=bytes.len() {
+        let mut parser = AnsiParser::new();
+        let mut lines = parser.push(&bytes[..split]).unwrap();
+        lines.extend(parser.push(&bytes[split..]).unwrap());
+        lines.extend(parser.finish().unwrap());
+        assert_eq!(lines, text.lines, "split at {split}");
+    }
+
+    let mut parser = AnsiParser::new();
+    let mut lines = Vec::new();
+    for byte in bytes {
+        lines.extend(parser.push(&[*byte]).unwrap());
+    }
+    assert_eq!(parser.style(), Style::reset());
+    lines.extend(parser.finish().unwrap());
+    assert_eq!(lines, text.lines);
+
+    // The virtual screen is only complete at the end
+    let options = ParseOptions::new().virtual_screen(10, None);
+    let mut parser = AnsiParser::with_options(&options);
+    assert_eq!(parser.push(b"one\ntwo\n\x1b[2Aun\n").unwrap(), []);
+    assert_eq!(
+        parser.finish().unwrap(),
+        [Line::from("une"), Line::from("two")]
+    );
+
+    // A limit stops the parse, the screen still ends up in the text
+    for (limits, bytes) in [
+        (Limits::new().max_output_len(3), b"abcdef\nxyz\n".as_slice()),
+        (Limits::new().max_lines(1), b"abc\nxyz\n"),
+    ] {
+        let options = ParseOptions::new().limits(limits).virtual_screen(80, None);
+        let text = bytes.into_text_with(&options).unwrap();
+        assert_eq!(text.lines, [Line::from("abc")]);
+        let mut parser = AnsiParser::with_options(&options);
+        assert_eq!(parser.push(bytes).unwrap(), []);
+        assert_eq!(parser.push(b"more\n").unwrap(), []);
+        assert_eq!(parser.finish().unwrap(), text.lines);
+    }
+}
+
+#[test]
+fn ansi_parser_errors_point_into_the_whole_input() {
+    let mut parser = AnsiParser::new();
+    assert_eq!(parser.push(b"ok\nb").unwrap(), [Line::from("ok")]);
+    let Err(Error::Utf8Error(e)) = parser.push(b"a\xffd\nfine\n") else {
+        panic!("expected a UTF-8 error");
+    };
+    assert_eq!(e.valid_up_to(), 5);
+    assert_eq!((e.position().line, e.position().column), (2, 3));
+    // Only the failed line is skipped, the others come with the next chunk
+    let lines = parser.push(b"next\n").unwrap();
+    assert_eq!(lines, [Line::from("fine"), Line::from("next")]);
+    assert_eq!(parser.finish().unwrap(), []);
+
+    let mut parser = AnsiParser::new();
+    assert!(parser.push(b"good\nb\xff\nfine\n").is_err());
+    let lines = parser.finish().unwrap();
+    assert_eq!(lines, [Line::from("good"), Line::from("fine")]);
+
+    // A lone `\r` completes a line, and the columns count from the `\n` before it
+    let mut parser = AnsiParser::new();
+    assert_eq!(parser.push(b"ab\r").unwrap(), []);
+    assert_eq!(parser.push(b"c").unwrap(), [Line::from("ab")]);
+    let Err(Error::Utf8Error(e)) = parser.push(b"\xff\n") else {
+        panic!("expected a UTF-8 error");
+    };
+    assert_eq!((e.position().line, e.position().column), (1, 5));
+}
+
 #[track_caller]
 fn test_both(bytes: impl AsRef<[u8]>, other: Text) {
+    test_both_with(bytes, &ParseOptions::default(), other);
+}
+
+#[track_caller]
+fn test_both_with(bytes: impl AsRef<[u8]>, options: &ParseOptions, other: Text) {
     let bytes = bytes.as_ref();
 
     #[cfg(feature = "zero-copy")]
-    let zero_copy = bytes.to_text().unwrap();
+    let zero_copy = bytes.to_text_with(options).unwrap();
 
-    let owned = bytes.into_text().unwrap();
+    let owned = bytes.into_text_with(options).unwrap();
 
     #[cfg(feature = "zero-copy")]
     assert_eq!(
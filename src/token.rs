@@ -0,0 +1,164 @@
+use crate::ParseOptions;
+
+/// A lexical token of a byte stream containing ANSI escape sequences.
+///
+/// Use [`Tokens`] to split input into tokens. This is the same grammar that
+/// [`IntoText`](crate::IntoText) uses, so you can inspect, filter or rewrite escape sequences
+/// without re-implementing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Token<'a> {
+    /// Printable text, without any control characters.
+    Text(&'a str),
+    /// Bytes that are not valid UTF-8.
+    Invalid(&'a [u8]),
+    /// A Select Graphic Rendition control sequence (`ESC [ ... m`) with its parameters.
+    Sgr(Vec<Param>),
+    /// Any other control sequence (`ESC [ ...`).
+    Csi {
+        /// The private marker (`<`, `=`, `>` or `?`) before the parameters, e.g. the `?` in
+        /// `ESC [ ? 25 h`.
+        private_marker: Option<u8>,
+        /// The parameters.
+        params: Vec<Param>,
+        /// The intermediate bytes (`0x20..=0x2F`) between the parameters and the final byte.
+        intermediates: &'a [u8],
+        /// The final byte (`0x40..=0x7E`) that selects the control function.
+        final_byte: u8,
+    },
+    /// An operating system command (`ESC ] ... BEL` or `ESC ] ... ESC \`), without its
+    /// introducer and terminator.
+    ///
+    /// An OSC that isn't terminated before a newline or another escape sequence is returned as
+    /// [`Token::Escape`] instead.
+    Osc(&'a [u8]),
+    /// A control string such as a sixel image, a kitty graphics command or a tmux passthrough,
+    /// without its introducer and the string terminator (`ESC \`).
+    ///
+    /// Line breaks are part of the string. A control string that isn't terminated before
+    /// another escape sequence is returned as [`Token::Escape`] instead.
+    ControlString {
+        /// The kind of control string.
+        kind: ControlStringKind,
+        /// The contents of the string.
+        payload: &'a [u8],
+    },
+    /// Any other escape sequence, or a malformed or truncated one, as raw bytes including the
+    /// `ESC` (or the C1 control that introduced it).
+    Escape(&'a [u8]),
+    /// A C0 control character (or `DEL`) other than `ESC` and line feed, e.g. `\t`, `\r` or
+    /// `\x08`.
+    Control(u8),
+    /// A line feed (`\n` or `\r\n`).
+    Newline,
+}
+
+/// The kind of a [`Token::ControlString`], selected by its introducer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControlStringKind {
+    /// Device control string (`ESC P`), e.g. sixel graphics, DECRQSS or tmux passthrough.
+    Dcs,
+    /// Application program command (`ESC _`), e.g. kitty graphics.
+    Apc,
+    /// Privacy message (`ESC ^`).
+    Pm,
+    /// Start of string (`ESC X`).
+    Sos,
+}
+
+/// A single control sequence parameter and its colon separated sub-parameters.
+///
+/// Following ECMA-48, an empty (sub-)parameter is `0`. Values that don't fit into a `u16` are
+/// clamped to `u16::MAX`.
+///
+/// For example, `ESC [ 1 ; 38 : 2 : : 255 : 0 : 0 m` has the parameters `1` and `38`, where
+/// `38` has the sub-parameters `[2, 0, 255, 0, 0]`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Param {
+    /// The value of the parameter.
+    pub value: u16,
+    /// The colon separated sub-parameters following the value.
+    pub subparams: Vec<u16>,
+}
+
+impl Param {
+    /// Create a parameter without sub-parameters.
+    pub fn new(value: u16) -> Self {
+        Self {
+            value,
+            subparams: Vec::new(),
+        }
+    }
+
+    /// Create a parameter with sub-parameters.
+    pub fn with_subparams(value: u16, subparams: impl Into<Vec<u16>>) -> Self {
+        Self {
+            value,
+            subparams: subparams.into(),
+        }
+    }
+}
+
+/// An iterator over the [`Token`]s of some bytes.
+///
+/// # Example
+///
+/// ```rust
+/// use ansi_to_tui::{Param, Token, Tokens};
+///
+/// let tokens: Vec<_> = Tokens::new(b"\x1b[1mbold\x1b[?25h\n").collect();
+/// assert_eq!(
+///     tokens,
+///     [
+///         Token::Sgr(vec![Param::new(1)]),
+///         Token::Text("bold"),
+///         Token::Csi {
+///             private_marker: Some(b'?'),
+///             params: vec![Param::new(25)],
+///             intermediates: b"",
+///             final_byte: b'h',
+///         },
+///         Token::Newline,
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+    input: &'a [u8],
+    options: ParseOptions,
+}
+
+impl<'a> Tokens<'a> {
+    /// Create an iterator over the tokens of `input`.
+    pub fn new(input: &'a [u8]) -> Self {
+        Self::with_options(input, &ParseOptions::default())
+    }
+
+    /// Create an iterator over the tokens of `input` that follows `options`, e.g. to recognize
+    /// C1 controls.
+    pub fn with_options(input: &'a [u8], options: &ParseOptions) -> Self {
+        Self {
+            input,
+            options: options.clone(),
+        }
+    }
+
+    /// The input that hasn't been tokenized yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.input
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() {
+            return None;
+        }
+        let (rest, token) = crate::parser::token(self.input, &self.options).ok()?;
+        self.input = rest;
+        Some(token)
+    }
+}